@@ -1,18 +1,48 @@
 //! Provides an API Client for the Canvas Data API.
 
+use bandwidth::BandwidthLimiter;
 use base64::encode as B64Encode;
 use chrono::prelude::*;
 use errors::*;
+use flate2::read::GzDecoder;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use rayon::prelude::*;
 use regex::Regex;
-use reqwest::{Client as HttpClient, Method, Request};
-use reqwest::header::HeaderValue;
+use reqwest::{Certificate, Client as HttpClient, Method, Proxy, Request, Response, StatusCode, Url};
+use reqwest::header::{HeaderValue, CONTENT_RANGE, DATE, RANGE, RETRY_AFTER};
 use ring::{digest, hmac};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
 use settings::Settings;
-use std::collections::BTreeMap;
-use std::fs::{self, File};
-use std::io;
-use std::path::Path;
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The directory under the save location that sync files are saved to.
+pub const SYNC_DIRECTORY: &'static str = "sync";
+/// The directory under the save location that per table history files are saved to.
+pub const TABLES_DIRECTORY: &'static str = "tables";
+/// The directory under the save location that the download cache is kept in.
+pub const DOWNLOAD_CACHE_DIRECTORY: &'static str = "cache";
+/// What's added to the path of a downloaded file to get the file recording that it was verified.
+pub const VERIFIED_FILE_SUFFIX: &'static str = ".verified";
+
+/// How far the local clock can drift from the server's before it's reported as the likely
+/// cause of an authentication failure.
+const MAX_CLOCK_SKEW_SECS: i64 = 60;
+/// The line that starts a certificate in a PEM file.
+const PEM_CERTIFICATE_BEGIN: &'static str = "-----BEGIN CERTIFICATE-----";
+/// The line that ends a certificate in a PEM file.
+const PEM_CERTIFICATE_END: &'static str = "-----END CERTIFICATE-----";
 
 lazy_static! {
   static ref REQREG: Regex = Regex::new(r"^requests.*?$").expect("Invalid Static Requests Regex");
@@ -25,8 +55,32 @@ pub struct CanvasDataApiClient {
   api_key: String,
   /// The API Secret to use for Canvas Data.
   api_secret: String,
+  /// The Base URL (scheme, host, and port) of the Canvas Data API.
+  api_url: String,
   /// The place to save files.
   save_location: String,
+  /// The maximum number of times to retry a failed request.
+  max_retries: u32,
+  /// The delay before the first retry, in milliseconds.
+  retry_base_delay_ms: u64,
+  /// The maximum delay between retries, in milliseconds.
+  retry_max_delay_ms: u64,
+  /// The pool downloads run on, sized to the maximum number of concurrent downloads.
+  download_pool: Arc<ThreadPool>,
+  /// Limits the bandwidth used by downloads, if there's a limit.
+  bandwidth_limiter: Option<Arc<BandwidthLimiter>>,
+  /// The maximum number of downloaded tables waiting to be imported.
+  max_pending_tables: usize,
+  /// Whether to cache schema definitions on disk under the save location.
+  should_cache_schemas_on_disk: bool,
+  /// Whether to reuse files already downloaded under another dump through the download cache.
+  should_cache_downloads: bool,
+  /// The schema definitions we've already fetched, keyed by version.
+  schema_cache: Arc<RwLock<BTreeMap<String, SchemaDefinition>>>,
+  /// The latest schema version, once we've fetched it for this run.
+  latest_schema_version: Arc<RwLock<Option<String>>>,
+  /// The raw API responses recorded this run, if recording is enabled.
+  recorded_responses: Option<Arc<RwLock<RecordedResponses>>>,
   /// The Reqwest Client,
   client: HttpClient,
 }
@@ -34,16 +88,53 @@ pub struct CanvasDataApiClient {
 impl CanvasDataApiClient {
   /// Creates a new Canvas Data API Client.
   ///
-  /// Creates a Canvas Data API Client that talks to the core portal.inshosteddata.com.
+  /// Creates a Canvas Data API Client that talks to the configured API URL, which
+  /// defaults to the core portal.inshosteddata.com.
   ///
   /// * `settings` - The settings to use for this API Client.
-  pub fn new(settings: &Settings) -> Self {
-    CanvasDataApiClient {
+  pub fn new(settings: &Settings) -> Result<Self> {
+    let client = try!(build_http_client(settings));
+
+    let download_pool = try!(
+      ThreadPoolBuilder::new()
+        .num_threads(settings.get_max_concurrent_downloads())
+        .build()
+    );
+
+    Ok(CanvasDataApiClient {
       api_key: settings.get_canvas_data_api_key(),
       api_secret: settings.get_canvas_data_api_secret(),
+      api_url: settings.get_canvas_data_api_url(),
       save_location: settings.get_save_location(),
-      client: HttpClient::new(),
-    }
+      max_retries: settings.get_http_max_retries(),
+      retry_base_delay_ms: settings.get_http_retry_base_delay_ms(),
+      retry_max_delay_ms: settings.get_http_retry_max_delay_ms(),
+      download_pool: Arc::new(download_pool),
+      bandwidth_limiter: settings
+        .get_max_download_bytes_per_second()
+        .map(|bytes_per_second| Arc::new(BandwidthLimiter::new(bytes_per_second))),
+      max_pending_tables: settings.get_max_pending_tables(),
+      should_cache_schemas_on_disk: settings.get_should_cache_schemas_on_disk(),
+      should_cache_downloads: settings.get_should_cache_downloads(),
+      schema_cache: Arc::new(RwLock::new(BTreeMap::new())),
+      latest_schema_version: Arc::new(RwLock::new(None)),
+      recorded_responses: if settings.get_should_record_api_responses() {
+        Some(Arc::new(RwLock::new(RecordedResponses::default())))
+      } else {
+        None
+      },
+      client: client,
+    })
+  }
+
+  /// Creates a copy of this client that never uses the download cache.
+  ///
+  /// The download cache is keyed by file name, so files whose names don't identify their contents
+  /// can't go through it.
+  pub fn without_download_cache(&self) -> Self {
+    let mut api_client = self.clone();
+    api_client.should_cache_downloads = false;
+    api_client
   }
 
   /// Computes the authorization header.
@@ -95,27 +186,34 @@ impl CanvasDataApiClient {
     Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
   }
 
-  /// Determines if a dump is a historical refresh based on the files in dump response.
+  /// Builds a signed GET Request for the Canvas Data API.
   ///
-  /// * `resp` - The Files in dump response to check.
-  pub fn is_historical_refresh(&self, resp: FilesInDumpResponse) -> bool {
-    let mut has_found_all_requests_table = true;
-    'outer: for artifact in resp.artifacts_by_table.values() {
-      for file in artifact.files.iter() {
-        if !REQREG.is_match(&file.filename) {
-          has_found_all_requests_table = false;
-          break 'outer;
-        }
-      }
-    }
-    has_found_all_requests_table
-  }
+  /// The Host that gets signed is taken from the configured API URL, including the port when
+  /// it isn't the default for the scheme, so it always matches the Host Header that gets sent.
+  /// Query Parameters are sorted by name before they're signed, as the API expects.
+  ///
+  /// * `path` - The path of the API endpoint to request.
+  /// * `query_params` - The query parameters to send with the request.
+  fn build_signed_request(&self, path: &str, query_params: &[(&str, String)]) -> Result<Request> {
+    let mut sorted_params = query_params.to_vec();
+    sorted_params.sort();
+    let query_string = sorted_params
+      .iter()
+      .map(|&(name, ref value)| format!("{}={}", name, value))
+      .collect::<Vec<_>>()
+      .join("&");
 
-  /// Gets a current list of Dumps for your Canvas Data Instance.
-  pub fn get_dumps(&self) -> Result<Vec<DumpInList>> {
-    trace!("Get Dumps was called.");
+    let mut uri: Url = try!(format!("{}{}", self.api_url.trim_right_matches('/'), path).parse());
+    if !query_string.is_empty() {
+      uri.set_query(Some(&query_string));
+    }
+    let host = match (uri.host_str(), uri.port()) {
+      (Some(host), Some(port)) => format!("{}:{}", host, port),
+      (Some(host), None) => host.to_owned(),
+      (None, _) => return Err(ErrorKind::InvalidApiUrl(self.api_url.clone()).into()),
+    };
+    let signed_path = uri.path().to_owned();
 
-    let uri = try!("https://portal.inshosteddata.com/api/account/self/dump".parse());
     let mut req: Request = Request::new(Method::GET, uri);
     let date_str = self.get_current_date();
     req.headers_mut().insert("Date", HeaderValue::from_str(&date_str).expect("Couldn't turn string into header value!"));
@@ -127,182 +225,1202 @@ impl CanvasDataApiClient {
       "Authorization",
       HeaderValue::from_str(&self.compute_auth_header(
         "GET",
-        "portal.inshosteddata.com",
+        &host,
         "application/json",
         "",
-        "/api/account/self/dump",
-        "",
+        &signed_path,
+        &query_string,
         &date_str,
       )).expect("Couldn't turn string into header value!"),
     );
 
-    Ok(try!(self.client.execute(req).and_then(|mut res| {
-      res.json()
-    }).map_err(|e| {
-      io::Error::new(io::ErrorKind::Other, e)
-    })))
+    Ok(req)
+  }
+
+  /// Determines if a dump is a historical refresh based on the files in dump response.
+  ///
+  /// * `resp` - The Files in dump response to check.
+  pub fn is_historical_refresh(&self, resp: FilesInDumpResponse) -> bool {
+    resp.is_historical_refresh()
+  }
+
+  /// Runs an operation, retrying it with a capped exponential backoff on transient failures.
+  ///
+  /// Connection errors, timeouts, and transient HTTP Statuses (429, and 5xx) are retried. A
+  /// `Retry-After` header sent with a 429, or 503 is honoured instead of the computed backoff, up
+  /// to the configured max delay.
+  ///
+  /// * `description` - What this operation is, used for logging.
+  /// * `operation` - The operation to run. It's called once per attempt.
+  pub fn with_retries<T, F>(&self, description: &str, mut operation: F) -> Result<T>
+  where
+    F: FnMut() -> Result<T>,
+  {
+    let mut attempt = 0;
+    loop {
+      let err = match operation() {
+        Ok(value) => return Ok(value),
+        Err(err) => err,
+      };
+
+      let retry_after = match *err.kind() {
+        ErrorKind::TransientHttpStatus(_, retry_after) => retry_after.map(Duration::from_secs),
+        ErrorKind::ApiUnavailableErr(_, _, _, retry_after) => retry_after.map(Duration::from_secs),
+        ErrorKind::IncompleteDownload(..) | ErrorKind::CorruptDownload(_) => None,
+        ErrorKind::DownloadInterrupted(..) => None,
+        // An error with a status came from `error_for_status`, and the transient statuses were
+        // already turned into `TransientHttpStatus`, so only retry errors that never got one.
+        ErrorKind::HttpError(ref http_err) if http_err.status().is_none() && !http_err.is_serialization() => None,
+        _ => return Err(err),
+      };
+      if attempt >= self.max_retries {
+        error!("{} failed after {} attempts", description, attempt + 1);
+        return Err(err);
+      }
+
+      // Don't let a server stall us for longer than we'd ever back off on our own.
+      let delay = retry_after
+        .map(|retry_after| cmp::min(retry_after, Duration::from_millis(self.retry_max_delay_ms)))
+        .unwrap_or_else(|| self.get_backoff_delay(attempt));
+      warn!(
+        "{} failed on attempt {}: {}. Retrying in {:?}",
+        description,
+        attempt + 1,
+        err,
+        delay
+      );
+      thread::sleep(delay);
+      attempt += 1;
+    }
+  }
+
+  /// Gets how long to wait before the next retry.
+  ///
+  /// The base delay doubles on each attempt up to the configured max delay, and then half of
+  /// that delay is randomized so runs that failed together don't all retry together.
+  ///
+  /// * `attempt` - The zero based attempt that just failed.
+  fn get_backoff_delay(&self, attempt: u32) -> Duration {
+    let capped_ms = cmp::min(
+      self.retry_base_delay_ms.saturating_mul(1u64 << cmp::min(attempt, 32)),
+      self.retry_max_delay_ms,
+    );
+
+    let mut random_bytes = [0u8; 8];
+    if SystemRandom::new().fill(&mut random_bytes).is_err() {
+      return Duration::from_millis(capped_ms);
+    }
+    let random = random_bytes.iter().fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+
+    let half_ms = capped_ms / 2;
+    Duration::from_millis(half_ms + random % (half_ms + 1))
+  }
+
+  /// Performs a signed GET against the Canvas Data API, and returns the raw JSON response.
+  ///
+  /// * `path` - The path of the API endpoint to request.
+  /// * `query_params` - The query parameters to send with the request.
+  fn get_json_value(&self, path: &str, query_params: &[(&str, String)]) -> Result<Value> {
+    self.with_retries(path, || {
+      let req = try!(self.build_signed_request(path, query_params));
+      let mut res = try!(self.client.execute(req));
+      try!(check_api_status(path, &mut res));
+      Ok(try!(res.json()))
+    })
+  }
+
+  /// Performs a signed GET against the Canvas Data API, and parses the JSON response.
+  ///
+  /// * `path` - The path of the API endpoint to request.
+  /// * `query_params` - The query parameters to send with the request.
+  fn get_json<T: DeserializeOwned>(&self, path: &str, query_params: &[(&str, String)]) -> Result<T> {
+    Ok(try!(serde_json::from_value(try!(self.get_json_value(path, query_params)))))
+  }
+
+  /// Records a raw API response, if recording is enabled.
+  ///
+  /// * `record` - Adds the response to the recorded responses.
+  fn record_response<F: FnOnce(&mut RecordedResponses)>(&self, record: F) {
+    if let Some(ref recorded_responses) = self.recorded_responses {
+      record(&mut recorded_responses.write().expect("Recorded responses lock was poisoned!"));
+    }
+  }
+
+  /// Saves the raw API responses recorded for a dump into its directory under the save location.
+  ///
+  /// Writes `dumps.json` with every page of the dump list, `files.json` with the dump's file
+  /// listing, a `schema-{version}.json` for every schema version used this run, and the latest
+  /// schema again as `schema-latest.json`. Does nothing if recording is disabled.
+  ///
+  /// * `dump_id` - The Dump ID to save the recorded responses for.
+  pub fn save_recorded_responses(&self, dump_id: &str) -> Result<()> {
+    let recorded_responses = match self.recorded_responses {
+      Some(ref recorded_responses) => recorded_responses.read().expect("Recorded responses lock was poisoned!"),
+      None => return Ok(()),
+    };
+    let dump_directory = Path::new(&self.save_location).join(dump_id);
+    try!(fs::create_dir_all(&dump_directory));
+
+    let dumps: Vec<Value> = recorded_responses
+      .dump_pages
+      .iter()
+      .flat_map(|page| page.as_array().cloned().unwrap_or_default())
+      .collect();
+    try!(write_json_file(&dump_directory.join("dumps.json"), &Value::Array(dumps)));
+    if let Some(files) = recorded_responses.files.get(dump_id) {
+      try!(write_json_file(&dump_directory.join("files.json"), files));
+    }
+    for (version, schema) in recorded_responses.schemas.iter() {
+      try!(write_json_file(&dump_directory.join(format!("schema-{}.json", version)), schema));
+    }
+    let latest_schema = recorded_responses
+      .latest_schema_version
+      .as_ref()
+      .and_then(|version| recorded_responses.schemas.get(version));
+    if let Some(latest_schema) = latest_schema {
+      try!(write_json_file(&dump_directory.join("schema-latest.json"), latest_schema));
+    }
+
+    debug!("Saved the recorded API responses for dump: {} to: {:?}", dump_id, dump_directory);
+    Ok(())
+  }
+
+  /// Gets a single page of Dumps for your Canvas Data Instance.
+  ///
+  /// * `after` - Only return dumps with a sequence number after this one.
+  /// * `limit` - The maximum number of dumps to return.
+  pub fn get_dumps_page(&self, after: i64, limit: i64) -> Result<Vec<DumpInList>> {
+    trace!("Get Dumps Page was called with after: [ {} ], limit: [ {} ]", after, limit);
+
+    let page = try!(self.get_json_value(
+      "/api/account/self/dump",
+      &[("after", after.to_string()), ("limit", limit.to_string())],
+    ));
+    self.record_response(|recorded| recorded.dump_pages.push(page.clone()));
+    Ok(try!(serde_json::from_value(page)))
+  }
+
+  /// Iterates over every Dump for your Canvas Data Instance, fetching pages as needed.
+  pub fn iter_dumps(&self) -> DumpIterator {
+    DumpIterator::new(move |after, limit| self.get_dumps_page(after, limit))
+  }
+
+  /// Gets a current list of every Dump for your Canvas Data Instance.
+  pub fn get_dumps(&self) -> Result<Vec<DumpInList>> {
+    trace!("Get Dumps was called.");
+
+    self.iter_dumps().collect()
+  }
+
+  /// Gets the path a schema version is cached at on disk.
+  ///
+  /// * `version` - The schema version.
+  fn get_schema_cache_path(&self, version: &str) -> PathBuf {
+    Path::new(&self.save_location)
+      .join("schemas")
+      .join(format!("schema-{}.json", version))
+  }
+
+  /// Adds a schema definition to the in memory cache, and the disk cache if it's enabled.
+  ///
+  /// * `schema` - The schema definition to cache.
+  fn cache_schema(&self, schema: &SchemaDefinition) -> Result<()> {
+    if self.should_cache_schemas_on_disk {
+      let cache_path = self.get_schema_cache_path(&schema.version);
+      if !cache_path.exists() {
+        try!(fs::create_dir_all(format!("{}/schemas", self.save_location)));
+        let file = try!(File::create(&cache_path));
+        try!(serde_json::to_writer(file, schema));
+      }
+    }
+
+    self
+      .schema_cache
+      .write()
+      .expect("Schema cache lock was poisoned!")
+      .insert(schema.version.clone(), schema.clone());
+    Ok(())
+  }
+
+  /// Gets a specific version of the schema.
+  ///
+  /// Each version is only fetched once, and is then served from memory. If caching schemas on
+  /// disk is enabled, versions fetched on previous runs are read from disk instead of the API.
+  ///
+  /// * `version` - The schema version to get.
+  pub fn get_schema(&self, version: &str) -> Result<SchemaDefinition> {
+    trace!("Get schema was called for version: [ {} ]", version);
+
+    if let Some(schema) = self
+      .schema_cache
+      .read()
+      .expect("Schema cache lock was poisoned!")
+      .get(version)
+    {
+      return Ok(schema.clone());
+    }
+
+    let cache_path = self.get_schema_cache_path(version);
+    let raw_schema: Value = if self.should_cache_schemas_on_disk && cache_path.exists() {
+      debug!("Reading schema version: {} from: {:?}", version, cache_path);
+      try!(serde_json::from_reader(try!(File::open(&cache_path))))
+    } else {
+      try!(self.get_json_value(&format!("/api/schema/{}", version), &[]))
+    };
+    self.record_response(|recorded| {
+      recorded.schemas.insert(version.to_owned(), raw_schema.clone());
+    });
+    let schema: SchemaDefinition = try!(serde_json::from_value(raw_schema));
+
+    try!(self.cache_schema(&schema));
+    Ok(schema)
+  }
+
+  /// Gets the latest schema.
+  ///
+  /// The latest schema is only fetched once per run.
+  pub fn get_latest_schema(&self) -> Result<SchemaDefinition> {
+    trace!("Get latest schema was called");
+
+    let latest_version = self
+      .latest_schema_version
+      .read()
+      .expect("Latest schema version lock was poisoned!")
+      .clone();
+    if let Some(version) = latest_version {
+      return self.get_schema(&version);
+    }
+
+    let raw_schema = try!(self.get_json_value("/api/schema/latest", &[]));
+    let schema: SchemaDefinition = try!(serde_json::from_value(raw_schema.clone()));
+    self.record_response(|recorded| {
+      recorded.schemas.insert(schema.version.clone(), raw_schema);
+      recorded.latest_schema_version = Some(schema.version.clone());
+    });
+    try!(self.cache_schema(&schema));
+    *self
+      .latest_schema_version
+      .write()
+      .expect("Latest schema version lock was poisoned!") = Some(schema.version.clone());
+    Ok(schema)
+  }
+
+  /// Gets the Table Definition for a Specific Table from the latest schema.
+  ///
+  /// * `table_name` - The Table name to get the definition for.
+  pub fn get_table_definition(&self, table_name: String) -> Result<Option<TableDefinition>> {
+    trace!("get_table_definition was called for: [ {} ]", table_name);
+
+    Ok(try!(self.get_latest_schema()).get_table_definition(&table_name))
+  }
+
+  /// Gets the list of files for a specific dump.
+  ///
+  /// * `dump_id` - The Dump ID to grab the list of files for.
+  pub fn get_files_for_dump(&self, dump_id: String) -> Result<FilesInDumpResponse> {
+    trace!(
+      "Get files for dump was called with dump id: [ {} ]",
+      dump_id
+    );
+
+    let files = try!(self.get_json_value(&format!("/api/account/self/file/byDump/{}", dump_id), &[]));
+    self.record_response(|recorded| {
+      recorded.files.insert(dump_id.clone(), files.clone());
+    });
+    Ok(try!(serde_json::from_value(files)))
+  }
+
+  /// Downloads a single file, retrying on transient failures.
+  ///
+  /// The file is downloaded to a `.part` file next to the download path, and is only renamed
+  /// into place once it's the expected size, and is valid gzip. A file that's already in place
+  /// is only skipped if it's the size it was verified at, or is valid gzip. If the signed URL for
+  /// the file expires, the URLs are refreshed and the download picks up where it left off.
+  ///
+  /// * `filename` - The name of the file to download.
+  /// * `signed_urls` - The current signed URLs for the files being downloaded.
+  /// * `download_path` - Where to save the file.
+  fn download_file(&self, filename: &str, signed_urls: &SignedUrls, download_path: &str) -> Result<()> {
+    if Path::new(download_path).exists() {
+      if is_verified_download(download_path) {
+        debug!("{:?} exists, skipping file", download_path);
+        return Ok(());
+      }
+      warn!("{:?} exists, but isn't valid gzip. Downloading it again", download_path);
+      try!(fs::remove_file(download_path));
+    }
+
+    // Reuse the same file if it was already downloaded under another dump.
+    let cache_path = self.get_download_cache_path(filename);
+    if let Some(ref cache_path) = cache_path {
+      let cache_path_str = cache_path.to_string_lossy().into_owned();
+      if cache_path.exists() {
+        if is_verified_download(&cache_path_str) {
+          debug!("{:?} is in the download cache, reusing it", download_path);
+          try!(link_or_copy(cache_path, Path::new(download_path)));
+          record_verified_download(download_path);
+          return Ok(());
+        }
+        warn!("{:?} is in the download cache, but isn't valid gzip. Removing it", cache_path);
+        try!(fs::remove_file(cache_path));
+      }
+    }
+
+    debug!("{:?} does not exist, downloading file", download_path);
+    let part_path = format!("{}.part", download_path);
+    let mut refreshes = 0;
+    loop {
+      let file_to_download = BasicFile {
+        url: try!(signed_urls.get_url(filename)),
+        filename: filename.to_owned(),
+      };
+      let res = self.with_retries(filename, || {
+        self.download_to_part_file(&file_to_download, &part_path)
+      });
+      match res {
+        Ok(_) => break,
+        Err(Error(ErrorKind::ExpiredFileUrl(_), _)) if refreshes < self.max_retries => {
+          warn!("Signed URL for: {} has expired, refreshing URLs", filename);
+          try!(signed_urls.refresh(filename, &file_to_download.url));
+          refreshes += 1;
+        }
+        Err(err) => return Err(err),
+      }
+    }
+    try!(fs::rename(&part_path, download_path));
+    record_verified_download(download_path);
+
+    if let Some(ref cache_path) = cache_path {
+      match link_or_copy(Path::new(download_path), cache_path) {
+        Ok(_) => record_verified_download(&cache_path.to_string_lossy()),
+        Err(err) => warn!("Failed to add {:?} to the download cache: {}", download_path, err),
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Gets the path a file is kept at in the download cache, if the download cache is enabled.
+  ///
+  /// Files are kept under their full name, since the hash in a file name is short, and isn't
+  /// enough to tell the files of a table apart on its own.
+  ///
+  /// * `filename` - The name of the file, which has the table, shard, and hash of its contents.
+  fn get_download_cache_path(&self, filename: &str) -> Option<PathBuf> {
+    if !self.should_cache_downloads {
+      return None;
+    }
+
+    Some(Path::new(&self.save_location).join(DOWNLOAD_CACHE_DIRECTORY).join(filename))
+  }
+
+  /// Downloads a file into its `.part` file.
+  ///
+  /// If a `.part` file was left over from an interrupted download, only the rest of the file
+  /// is requested. If the server won't resume, the download starts over.
+  ///
+  /// * `file_to_download` - The file to download.
+  /// * `part_path` - The `.part` file to download to.
+  fn download_to_part_file(&self, file_to_download: &BasicFile, part_path: &str) -> Result<()> {
+    let already_downloaded = fs::metadata(part_path).map(|metadata| metadata.len()).unwrap_or(0);
+
+    let uri = try!(file_to_download.url.parse());
+    let mut req = Request::new(Method::GET, uri);
+    if already_downloaded > 0 {
+      debug!("Resuming {:?} from byte: {}", part_path, already_downloaded);
+      req.headers_mut().insert(
+        RANGE,
+        HeaderValue::from_str(&format!("bytes={}-", already_downloaded))
+          .expect("Couldn't turn string into header value!"),
+      );
+    }
+    let res = try!(self.client.execute(req));
+    try!(check_transient_status(&res));
+
+    // Signed URLs answer with a 403 once they've expired.
+    if res.status() == StatusCode::FORBIDDEN {
+      return Err(ErrorKind::ExpiredFileUrl(file_to_download.filename.clone()).into());
+    }
+
+    // The server says there's nothing left to send, so the `.part` file should be complete.
+    if already_downloaded > 0 && res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+      return check_part_file(&file_to_download.filename, part_path, None);
+    }
+    let mut res = try!(res.error_for_status());
+
+    let content_range = res
+      .headers()
+      .get(CONTENT_RANGE)
+      .and_then(|value| value.to_str().ok())
+      .and_then(parse_content_range);
+    let (mut file, expected_size) = match content_range {
+      Some((start, total)) if res.status() == StatusCode::PARTIAL_CONTENT && start == already_downloaded => {
+        (try!(OpenOptions::new().append(true).open(part_path)), total)
+      }
+      _ => {
+        if already_downloaded > 0 {
+          debug!("Couldn't resume {:?}, starting over", part_path);
+        }
+        (try!(File::create(part_path)), res.content_length())
+      }
+    };
+
+    try!(self.copy_to_file(&file_to_download.filename, &mut res, &mut file));
+    try!(file.sync_all());
+
+    check_part_file(&file_to_download.filename, part_path, expected_size)
+  }
+
+  /// Copies the body of a download into a file, staying under the bandwidth limit if there is one.
+  ///
+  /// * `filename` - The name of the file being downloaded.
+  /// * `res` - The response to copy the body of.
+  /// * `file` - The file to copy into.
+  fn copy_to_file(&self, filename: &str, res: &mut Response, file: &mut File) -> Result<u64> {
+    let bandwidth_limiter = match self.bandwidth_limiter {
+      Some(ref bandwidth_limiter) => bandwidth_limiter,
+      None => return Ok(try!(res.copy_to(file))),
+    };
+
+    let mut buffer = [0u8; 64 * 1024];
+    let mut copied = 0;
+    loop {
+      let read = try!(res.read(&mut buffer).map_err(|err| {
+        Error::from(ErrorKind::DownloadInterrupted(filename.to_owned(), err.to_string()))
+      }));
+      if read == 0 {
+        return Ok(copied);
+      }
+      bandwidth_limiter.consume(read as u64);
+      try!(file.write_all(&buffer[..read]));
+      copied += read as u64;
+    }
+  }
+
+  /// Downloads a list of files into a directory, skipping any that are already there.
+  ///
+  /// Each file is downloaded with `download_file`, so it's retried, resumed, and validated.
+  ///
+  /// * `save_location` - The directory to save the files in.
+  /// * `files` - The files to download.
+  /// * `refresh` - Lists the files again with freshly signed URLs, for when the URLs expire.
+  pub fn download_files(
+    &self,
+    save_location: &str,
+    files: &[BasicFile],
+    refresh: &(Fn() -> Result<Vec<BasicFile>> + Sync),
+  ) -> Result<()> {
+    try!(fs::create_dir_all(save_location));
+    let signed_urls = SignedUrls::new(files, refresh);
+
+    // Downloads run on their own pool, so the number of connections is the configured amount
+    // no matter how many cores the host has, or what else is running.
+    let results: Vec<Result<()>> = self.download_pool.install(|| {
+      files.par_iter().map(|file_to_download| {
+        let finalized_to_download_path = format!("{}/{}", save_location, &file_to_download.filename);
+        self.download_file(&file_to_download.filename, &signed_urls, &finalized_to_download_path)
+      }).collect()
+    });
+    for result in results {
+      try!(result);
+    }
+
+    Ok(())
+  }
+
+  /// Download all files for a specific dump.
+  ///
+  /// * `dump_id` - The Dump ID of the files to download.
+  pub fn download_files_for_dump(&self, dump_id: String) -> Result<()> {
+    trace!(
+      "Download files for dump was called with dump id: [ {} ]",
+      dump_id
+    );
+    let save_location = format!("{}/{}", self.save_location, &dump_id);
+    let files_in_dump = try!(self.get_files_for_dump(dump_id.clone()));
+
+    try!(self.download_files(
+      &save_location,
+      &files_in_dump.get_files(),
+      &|| self.get_files_for_dump(dump_id.clone()).map(|files_in_dump| files_in_dump.get_files()),
+    ));
+
+    trace!("Done Downloading Files for: {}", dump_id);
+
+    Ok(())
+  }
+
+  /// Downloads all files for a specific dump in the background, handing each table over once
+  /// all of its files are on disk.
+  ///
+  /// Files from every table are downloaded on the download pool at once, so a dump of small
+  /// tables still uses every connection. Tables can be imported while the rest of the dump
+  /// downloads. Once the maximum number of downloaded tables are waiting to be imported,
+  /// downloading waits for the import to catch up. Dropping the download stops it after the
+  /// files that are already downloading.
+  ///
+  /// * `dump_id` - The Dump ID of the files to download.
+  pub fn download_dump_by_table(&self, dump_id: String) -> DumpDownload {
+    trace!(
+      "Download dump by table was called with dump id: [ {} ]",
+      dump_id
+    );
+    let (sender, receiver) = sync_channel(self.max_pending_tables);
+    let api_client = self.clone();
+
+    let handle = thread::spawn(move || {
+      let files_in_dump = match api_client.get_files_for_dump(dump_id.clone()) {
+        Ok(files_in_dump) => files_in_dump,
+        Err(err) => {
+          let _ = sender.send(Err(err));
+          return;
+        }
+      };
+      let save_location = format!("{}/{}", api_client.save_location, &dump_id);
+      if let Err(err) = fs::create_dir_all(&save_location) {
+        let _ = sender.send(Err(err.into()));
+        return;
+      }
+
+      let artifacts: Vec<&ArtifactByTable> = files_in_dump.artifacts_by_table.values().collect();
+      let downloaded_table = |artifact: &ArtifactByTable| DownloadedTable {
+        table_name: artifact.table_name.clone(),
+        partial: artifact.partial,
+        paths: artifact
+          .files
+          .iter()
+          .map(|file| Path::new(&save_location).join(&file.filename))
+          .collect(),
+      };
+
+      // Every worker on the download pool can finish a table, so they share the sender.
+      let sender = Mutex::new(sender);
+      let is_stopped = AtomicBool::new(false);
+      let send = |result: Result<DownloadedTable>| {
+        // Stop if nobody is waiting for tables anymore.
+        if sender.lock().expect("Table sender lock was poisoned!").send(result).is_err() {
+          is_stopped.store(true, Ordering::SeqCst);
+        }
+      };
+
+      for artifact in artifacts.iter().filter(|artifact| artifact.files.is_empty()) {
+        send(Ok(downloaded_table(artifact)));
+      }
+
+      let files = files_in_dump.get_files();
+      let refresh = || api_client.get_files_for_dump(dump_id.clone()).map(|files_in_dump| files_in_dump.get_files());
+      let signed_urls = SignedUrls::new(&files, &refresh);
+      let table_files: Vec<(usize, &BasicFile)> = artifacts
+        .iter()
+        .enumerate()
+        .flat_map(|(pos, artifact)| artifact.files.iter().map(move |file| (pos, file)))
+        .collect();
+      let remaining_files: Vec<AtomicUsize> = artifacts
+        .iter()
+        .map(|artifact| AtomicUsize::new(artifact.files.len()))
+        .collect();
+
+      api_client.download_pool.install(|| {
+        table_files.par_iter().for_each(|&(pos, file_to_download)| {
+          if is_stopped.load(Ordering::SeqCst) {
+            return;
+          }
+
+          let finalized_to_download_path = format!("{}/{}", save_location, &file_to_download.filename);
+          match api_client.download_file(&file_to_download.filename, &signed_urls, &finalized_to_download_path) {
+            // Whoever downloads the last file of a table hands the table over.
+            Ok(_) => {
+              if remaining_files[pos].fetch_sub(1, Ordering::SeqCst) == 1 {
+                send(Ok(downloaded_table(artifacts[pos])));
+              }
+            }
+            Err(err) => {
+              is_stopped.store(true, Ordering::SeqCst);
+              send(Err(err));
+            }
+          }
+        })
+      });
+
+      trace!("Done Downloading Files for: {}", dump_id);
+    });
+
+    DumpDownload {
+      receiver: Some(receiver),
+      handle: Some(handle),
+    }
+  }
+
+  /// Gets the history of files for a specific table across every dump.
+  ///
+  /// * `table_name` - The table to grab the history of files for.
+  pub fn get_files_for_table(&self, table_name: &str) -> Result<FilesByTableResponse> {
+    trace!(
+      "Get files for table was called with table: [ {} ]",
+      table_name
+    );
+
+    self.get_json(&format!("/api/account/self/file/byTable/{}", table_name), &[])
+  }
+
+  /// Downloads the files of a single dump in a table's history.
+  ///
+  /// Returns the directory under the save location the files were saved to.
+  ///
+  /// * `table_name` - The table the history is for.
+  /// * `entry` - The entry in the table's history to download.
+  pub fn download_table_history(&self, table_name: &str, entry: &TableHistoryEntry) -> Result<String> {
+    trace!(
+      "Download table history was called for table: [ {} ], dump id: [ {} ]",
+      table_name,
+      entry.dump_id
+    );
+    let directory = format!("{}/{}/{}", TABLES_DIRECTORY, table_name, entry.dump_id);
+    try!(self.download_files(
+      &format!("{}/{}", self.save_location, directory),
+      &entry.files,
+      &|| {
+        self.get_files_for_table(table_name).map(|files_by_table| {
+          files_by_table
+            .history
+            .into_iter()
+            .find(|refreshed_entry| refreshed_entry.dump_id == entry.dump_id)
+            .map(|refreshed_entry| refreshed_entry.files)
+            .unwrap_or(Vec::new())
+        })
+      },
+    ));
+
+    Ok(directory)
+  }
+
+  /// Gets the minimal list of files that make up the current complete state of every table.
+  pub fn get_sync_files(&self) -> Result<SyncResponse> {
+    trace!("Get sync files was called.");
+
+    self.get_json("/api/account/self/file/sync", &[])
+  }
+
+  /// Downloads the current sync file set into the sync directory under the save location.
+  ///
+  /// Any file in the sync directory that's no longer part of the sync file set is removed, so
+  /// the directory always holds exactly the current state of every table.
+  pub fn download_sync_files(&self) -> Result<SyncResponse> {
+    trace!("Download sync files was called.");
+    let save_location = format!("{}/{}", self.save_location, SYNC_DIRECTORY);
+    let sync_response = try!(self.get_sync_files());
+
+    let files = sync_response.get_files();
+    try!(self.download_files(
+      &save_location,
+      &files,
+      &|| self.get_sync_files().map(|sync_response| sync_response.get_files()),
+    ));
+
+    // Remove anything that's no longer listed, along with the record that it was verified.
+    for entry in try!(fs::read_dir(&save_location)) {
+      let path = try!(entry).path();
+      let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("").to_owned();
+      let file_name = file_name.trim_right_matches(VERIFIED_FILE_SUFFIX);
+      if !files.iter().any(|file| file.filename == file_name) {
+        info!("Removing: {:?} since it's no longer in the sync file set", path);
+        try!(fs::remove_file(&path));
+      }
+    }
+
+    trace!("Done Downloading Sync Files");
+
+    Ok(sync_response)
+  }
+}
+
+/// The current signed URLs for a set of files being downloaded.
+///
+/// Signed URLs expire, so they can be refreshed by listing the files again.
+struct SignedUrls<'a> {
+  /// The current signed URL of each file, keyed by filename.
+  urls: RwLock<BTreeMap<String, String>>,
+  /// Lists the files again with freshly signed URLs.
+  refresh_with: &'a (Fn() -> Result<Vec<BasicFile>> + Sync),
+}
+
+impl<'a> SignedUrls<'a> {
+  /// Creates the signed URLs for a set of files.
+  ///
+  /// * `files` - The files being downloaded.
+  /// * `refresh_with` - Lists the files again with freshly signed URLs.
+  fn new(files: &[BasicFile], refresh_with: &'a (Fn() -> Result<Vec<BasicFile>> + Sync)) -> Self {
+    SignedUrls {
+      urls: RwLock::new(
+        files
+          .iter()
+          .map(|file| (file.filename.clone(), file.url.clone()))
+          .collect(),
+      ),
+      refresh_with: refresh_with,
+    }
+  }
+
+  /// Gets the current signed URL for a file.
+  ///
+  /// * `filename` - The file to get the URL for.
+  fn get_url(&self, filename: &str) -> Result<String> {
+    self
+      .urls
+      .read()
+      .expect("Signed URLs lock was poisoned!")
+      .get(filename)
+      .cloned()
+      .ok_or(ErrorKind::ExpiredFileUrl(filename.to_owned()).into())
+  }
+
+  /// Refreshes the signed URLs after one has expired.
+  ///
+  /// Many downloads notice the same expiry at once, so the URLs are only listed again if
+  /// nobody else has already replaced the expired one.
+  ///
+  /// * `filename` - The file whose URL expired.
+  /// * `expired_url` - The URL that expired.
+  fn refresh(&self, filename: &str, expired_url: &str) -> Result<()> {
+    let mut urls = self.urls.write().expect("Signed URLs lock was poisoned!");
+    if urls.get(filename).map(|url| url.as_str() != expired_url).unwrap_or(false) {
+      return Ok(());
+    }
+
+    for file in try!((self.refresh_with)()) {
+      urls.insert(file.filename, file.url);
+    }
+    Ok(())
+  }
+}
+
+/// A table whose files in a dump are all on disk.
+#[derive(Clone, Debug)]
+pub struct DownloadedTable {
+  /// The name of the table.
+  pub table_name: String,
+  /// Whether the files only hold part of the table, rather than the whole table.
+  pub partial: bool,
+  /// The paths of the table's downloaded files.
+  pub paths: Vec<PathBuf>,
+}
+
+/// A dump being downloaded in the background, iterated table by table as they finish.
+///
+/// Tables come out in the order they finish downloading, not the order they're listed in.
+pub struct DumpDownload {
+  /// Receives each table once it's downloaded.
+  receiver: Option<Receiver<Result<DownloadedTable>>>,
+  /// The background download thread.
+  handle: Option<JoinHandle<()>>,
+}
+
+impl Iterator for DumpDownload {
+  type Item = Result<DownloadedTable>;
+
+  fn next(&mut self) -> Option<Result<DownloadedTable>> {
+    self.receiver.as_ref().and_then(|receiver| receiver.recv().ok())
+  }
+}
+
+impl Drop for DumpDownload {
+  fn drop(&mut self) {
+    // Hang up first, so a download waiting for room stops instead of waiting forever.
+    self.receiver.take();
+    if let Some(handle) = self.handle.take() {
+      let _ = handle.join();
+    }
+  }
+}
+
+/// The raw API responses recorded during a run.
+#[derive(Default)]
+struct RecordedResponses {
+  /// Every page of the dump list, in the order they were fetched.
+  dump_pages: Vec<Value>,
+  /// The file listings, keyed by Dump ID.
+  files: BTreeMap<String, Value>,
+  /// The schema definitions, keyed by version.
+  schemas: BTreeMap<String, Value>,
+  /// The version of the latest schema.
+  latest_schema_version: Option<String>,
+}
+
+/// The number of dumps to ask for per page, which is the most the API will return.
+const DUMP_PAGE_LIMIT: i64 = 100;
+
+/// An Iterator over every Dump, walking through the pages of the list dumps endpoint.
+pub struct DumpIterator<'a> {
+  /// Fetches the page of dumps after a sequence number, with at most a limit of dumps.
+  get_page: Box<FnMut(i64, i64) -> Result<Vec<DumpInList>> + 'a>,
+  /// The sequence number of the last dump we've seen.
+  after: i64,
+  /// The dumps left from the current page.
+  page: VecDeque<DumpInList>,
+  /// Whether there are no more pages to fetch.
+  is_finished: bool,
+}
+
+impl<'a> DumpIterator<'a> {
+  /// Creates an Iterator that starts from the first dump.
+  ///
+  /// * `get_page` - Fetches the page of dumps after a sequence number, with at most a limit of dumps.
+  fn new<F>(get_page: F) -> Self
+  where
+    F: FnMut(i64, i64) -> Result<Vec<DumpInList>> + 'a,
+  {
+    DumpIterator {
+      get_page: Box::new(get_page),
+      after: 0,
+      page: VecDeque::new(),
+      is_finished: false,
+    }
+  }
+}
+
+impl<'a> Iterator for DumpIterator<'a> {
+  type Item = Result<DumpInList>;
+
+  fn next(&mut self) -> Option<Result<DumpInList>> {
+    loop {
+      if let Some(dump) = self.page.pop_front() {
+        return Some(Ok(dump));
+      }
+      if self.is_finished {
+        return None;
+      }
+
+      let page = match (self.get_page)(self.after, DUMP_PAGE_LIMIT) {
+        Ok(page) => page,
+        Err(err) => {
+          self.is_finished = true;
+          return Some(Err(err));
+        }
+      };
+      debug!("Got page of {} dumps after sequence: {}", page.len(), self.after);
+
+      // The API may return fewer dumps than asked for before the end of the list, so only an
+      // empty page is the last page. Also stop if the API didn't move us forward so we can never
+      // loop on the same page.
+      let last_sequence = page.iter().map(|dump| dump.sequence).max();
+      match last_sequence {
+        Some(sequence) if sequence > self.after => {
+          self.after = sequence;
+        }
+        _ => {
+          self.is_finished = true;
+        }
+      }
+      self.page.extend(page);
+    }
+  }
+}
+
+/// Builds an HTTP Client with the configured timeouts, proxy, and extra CA certificates.
+///
+/// * `settings` - The settings to configure the HTTP Client with.
+pub fn build_http_client(settings: &Settings) -> Result<HttpClient> {
+  let mut client_builder = HttpClient::builder()
+    .connect_timeout(Duration::from_secs(settings.get_http_connect_timeout_secs()))
+    .timeout(Duration::from_secs(settings.get_http_read_timeout_secs()));
+  if let Some(proxy_url) = settings.get_http_proxy_url() {
+    let mut proxy = try!(Proxy::all(proxy_url.as_str()).chain_err(|| ErrorKind::InvalidProxyUrl(proxy_url.clone())));
+    if let Some((username, password)) = settings.get_http_proxy_credentials() {
+      proxy = proxy.basic_auth(&username, &password);
+    }
+    client_builder = client_builder.proxy(proxy);
+  }
+  for ca_certificate_path in settings.get_http_ca_certificates() {
+    for certificate in try!(load_ca_certificates(&ca_certificate_path)) {
+      client_builder = client_builder.add_root_certificate(certificate);
+    }
+  }
+  Ok(try!(client_builder.build()))
+}
+
+/// Loads every CA certificate in a PEM file.
+///
+/// * `path` - The path of the PEM file, which may hold a bundle of several certificates.
+fn load_ca_certificates(path: &str) -> Result<Vec<Certificate>> {
+  let mut pem = String::new();
+  try!(
+    File::open(path)
+      .and_then(|mut file| file.read_to_string(&mut pem))
+      .chain_err(|| ErrorKind::InvalidCaCertificate(path.to_owned()))
+  );
+
+  let mut certificates = Vec::new();
+  for block in pem.split_terminator(PEM_CERTIFICATE_END) {
+    let begin = match block.find(PEM_CERTIFICATE_BEGIN) {
+      Some(begin) => begin,
+      None => continue,
+    };
+    let certificate_pem = format!("{}{}\n", &block[begin..], PEM_CERTIFICATE_END);
+    certificates.push(try!(
+      Certificate::from_pem(certificate_pem.as_bytes()).chain_err(|| ErrorKind::InvalidCaCertificate(path.to_owned()))
+    ));
+  }
+  if certificates.is_empty() {
+    return Err(ErrorKind::InvalidCaCertificate(path.to_owned()).into());
   }
 
-  /// Gets the latest schema.
-  pub fn get_latest_schema(&self) -> Result<SchemaDefinition> {
-    trace!("Get latest schema was called");
+  Ok(certificates)
+}
 
-    let uri = try!("https://portal.inshosteddata.com/api/schema/latest".parse());
-    let mut req: Request = Request::new(Method::GET, uri);
-    let date_str = self.get_current_date();
-    req.headers_mut().insert("Date", HeaderValue::from_str(&date_str).expect("Failed to turn string into header value!"));
-    req.headers_mut().insert(
-      "Content-Type",
-      HeaderValue::from_static("application/json"),
-    );
-    req.headers_mut().insert(
-      "Authorization",
-      HeaderValue::from_str(&self.compute_auth_header(
-        "GET",
-        "portal.inshosteddata.com",
-        "application/json",
-        "",
-        "/api/schema/latest",
-        "",
-        &date_str,
-      )).expect("Failed to turn string into header value!"),
-    );
+/// Writes a JSON value to a file, pretty printed so it's easy to read.
+///
+/// * `path` - The path of the file to write.
+/// * `value` - The JSON value to write.
+fn write_json_file(path: &Path, value: &Value) -> Result<()> {
+  let file = try!(File::create(path));
+  Ok(try!(serde_json::to_writer_pretty(file, value)))
+}
 
-    Ok(try!(self.client.execute(req).and_then(|mut res| {
-      res.json()
-    }).map_err(|e| {
-      io::Error::new(io::ErrorKind::Other, e)
-    })))
+/// Hard links a file to a new path, or copies it there if it can't be hard linked.
+///
+/// Does nothing if there's already a file at the new path.
+///
+/// * `from` - The file to link.
+/// * `to` - The path to link the file to.
+fn link_or_copy(from: &Path, to: &Path) -> Result<()> {
+  if let Some(parent) = to.parent() {
+    try!(fs::create_dir_all(parent));
+  }
+  match fs::hard_link(from, to) {
+    Ok(_) => return Ok(()),
+    Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => return Ok(()),
+    Err(err) => debug!("Couldn't hard link {:?} to {:?}: {}, copying it instead", from, to, err),
   }
 
-  /// Gets the Table Definition for a Specific Table.
-  ///
-  /// * `table_name` - The Table name to get the definition for.
-  pub fn get_table_definition(&self, table_name: String) -> Result<Option<TableDefinition>> {
-    trace!("get_table_definition was called for: [ {} ]", table_name);
+  // Copy next to the destination first, so nothing ever sees a partial copy.
+  let part_path = format!("{}.part", to.to_string_lossy());
+  try!(fs::copy(from, &part_path));
+  try!(fs::rename(&part_path, to));
+  Ok(())
+}
 
-    let uri = try!("https://portal.inshosteddata.com/api/schema/latest".parse());
-    let mut req: Request = Request::new(Method::GET, uri);
-    let date_str = self.get_current_date();
-    req.headers_mut().insert("Date", HeaderValue::from_str(&date_str).expect("Failed to turn string into headervalue!"));
-    req.headers_mut().insert(
-      "Content-Type",
-      HeaderValue::from_static("application/json"),
-    );
-    req.headers_mut().insert(
-      "Authorization",
-      HeaderValue::from_str(&self.compute_auth_header(
-        "GET",
-        "portal.inshosteddata.com",
-        "application/json",
-        "",
-        "/api/schema/latest",
-        "",
-        &date_str,
-      )).expect("Failed to turn string into headervalue!"),
-    );
+/// Checks if a file is a complete, valid gzip file.
+///
+/// Decompressing the whole file checks the CRC, and uncompressed size stored at the end of it.
+///
+/// * `path` - The path of the file to check.
+fn is_valid_gzip(path: &str) -> bool {
+  File::open(path)
+    .and_then(|file| {
+      io::copy(&mut GzDecoder::new(BufReader::new(file)), &mut io::sink())
+    })
+    .is_ok()
+}
 
-    Ok(try!(self.client.execute(req).and_then(|mut res| {
-      res.json()
-    }).map(|res: SchemaDefinition| {
-      let mut ret = None;
+/// Gets what's recorded about a verified file: its size, and when it was last modified.
+///
+/// * `path` - The path of the file.
+fn get_verified_record(path: &str) -> io::Result<String> {
+  let metadata = try!(fs::metadata(path));
+  let modified = try!(metadata.modified())
+    .duration_since(UNIX_EPOCH)
+    .map(|modified| format!("{}.{:09}", modified.as_secs(), modified.subsec_nanos()))
+    .unwrap_or(String::new());
+  Ok(format!("{} {}", metadata.len(), modified))
+}
 
-      for table_def in res.schema.values() {
-        if table_def.table_name.to_lowercase() == table_name {
-          ret = Some(table_def.clone());
-          break;
-        }
-      }
+/// Checks if a file that's already in place is a complete download.
+///
+/// Decompressing every file on every run is slow, so once a file is verified its size, and
+/// modification time are recorded next to it, and a file that still matches the record is
+/// trusted. A file that was replaced, or changed since has to be valid gzip again.
+///
+/// * `path` - The path of the file to check.
+fn is_verified_download(path: &str) -> bool {
+  let actual_record = match get_verified_record(path) {
+    Ok(actual_record) => actual_record,
+    Err(_) => return false,
+  };
+  let verified_record = fs::read_to_string(format!("{}{}", path, VERIFIED_FILE_SUFFIX)).ok();
+  if verified_record.as_ref().map(|verified_record| verified_record.trim()) == Some(actual_record.as_str()) {
+    return true;
+  }
 
-      ret
-    }).map_err(|e| {
-      io::Error::new(io::ErrorKind::Other, e)
-    })))
+  if !is_valid_gzip(path) {
+    return false;
   }
+  record_verified_download(path);
+  true
+}
 
-  /// Gets the list of files for a specific dump.
-  ///
-  /// * `dump_id` - The Dump ID to grab the list of files for.
-  pub fn get_files_for_dump(&self, dump_id: String) -> Result<FilesInDumpResponse> {
-    trace!(
-      "Get files for dump was called with dump id: [ {} ]",
-      dump_id
-    );
+/// Records that a file in place is a complete download, along with its size, and modification time.
+///
+/// Failing to record it only means the file is decompressed to check it again next time.
+///
+/// * `path` - The path of the verified file.
+fn record_verified_download(path: &str) {
+  let result = get_verified_record(path).and_then(|verified_record| {
+    fs::write(format!("{}{}", path, VERIFIED_FILE_SUFFIX), verified_record)
+  });
+  if let Err(err) = result {
+    warn!("Failed to record that {:?} was verified: {}", path, err);
+  }
+}
 
-    let path = format!("/api/account/self/file/byDump/{}", dump_id);
-    let uri = try!(format!("https://portal.inshosteddata.com{}", &path).parse());
-    let mut req: Request = Request::new(Method::GET, uri);
-    let date_str = self.get_current_date();
-    req.headers_mut().insert("Date", HeaderValue::from_str(&date_str).expect("Failed to turn string into header value!"));
-    req.headers_mut().insert(
-      "Content-Type",
-      HeaderValue::from_static("application/json"),
-    );
-    req.headers_mut().insert(
-      "Authorization",
-      HeaderValue::from_str(&self.compute_auth_header(
-        "GET",
-        "portal.inshosteddata.com",
-        "application/json",
-        "",
-        &path,
-        "",
-        &date_str,
-      )).expect("Failed to turn string into headervalue!"),
-    );
+/// Checks that a downloaded `.part` file is the expected size, and is valid gzip.
+///
+/// A `.part` file that isn't valid gzip is removed so the next attempt starts over.
+///
+/// * `filename` - The name of the file being downloaded.
+/// * `part_path` - The path of the `.part` file.
+/// * `expected_size` - The size the server said the file is, if it said.
+fn check_part_file(filename: &str, part_path: &str, expected_size: Option<u64>) -> Result<()> {
+  let actual_size = try!(fs::metadata(part_path)).len();
+  if let Some(expected_size) = expected_size {
+    if actual_size != expected_size {
+      return Err(
+        ErrorKind::IncompleteDownload(filename.to_owned(), actual_size, expected_size).into(),
+      );
+    }
+  }
 
-    Ok(try!(self.client.execute(req).and_then(|mut res| {
-      res.json()
-    }).map_err(|e| {
-      io::Error::new(io::ErrorKind::Other, e)
-    })))
+  if !is_valid_gzip(part_path) {
+    try!(fs::remove_file(part_path));
+    return Err(ErrorKind::CorruptDownload(filename.to_owned()).into());
   }
 
-  /// Download all files for a specific dump.
-  ///
-  /// * `dump_id` - The Dump ID of the files to download.
-  pub fn download_files_for_dump(&self, dump_id: String) -> Result<()> {
-    trace!(
-      "Download files for dump was called with dump id: [ {} ]",
-      dump_id
+  Ok(())
+}
+
+/// Parses a `Content-Range` header value into the start byte, and the total size.
+///
+/// * `value` - The value of the `Content-Range` header, e.g. `bytes 100-199/200`.
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+  let range = value.trim().trim_left_matches("bytes").trim();
+  let mut split = range.splitn(2, '/');
+  let start_and_end = split.next().unwrap_or("");
+  let total = split.next().and_then(|total| total.parse::<u64>().ok());
+  start_and_end
+    .split('-')
+    .next()
+    .and_then(|start| start.parse::<u64>().ok())
+    .map(|start| (start, total))
+}
+
+/// Checks if a response has a transient HTTP Status that's worth retrying.
+///
+/// * `res` - The response to check.
+fn check_transient_status(res: &Response) -> Result<()> {
+  let status = res.status();
+  if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+    return Ok(());
+  }
+
+  Err(ErrorKind::TransientHttpStatus(status.as_u16(), get_retry_after(res)).into())
+}
+
+/// Checks the HTTP Status of a Canvas Data API response, turning failures into typed errors.
+///
+/// The errors carry the status, endpoint, and response body. Authentication failures also
+/// carry a diagnostic of the likely cause.
+///
+/// * `endpoint` - The API endpoint that was requested.
+/// * `res` - The response to check.
+fn check_api_status(endpoint: &str, res: &mut Response) -> Result<()> {
+  let status = res.status();
+  if status.is_success() {
+    return Ok(());
+  }
+
+  let retry_after = get_retry_after(res);
+  let server_date = res
+    .headers()
+    .get(DATE)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+    .map(|server_date| server_date.with_timezone(&Utc));
+  let body = res.text().unwrap_or(String::new());
+
+  if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+    return Err(
+      ErrorKind::ApiAuthErr(
+        endpoint.to_owned(),
+        status.as_u16(),
+        body,
+        get_auth_diagnostic(server_date),
+      ).into(),
     );
-    let save_location = format!("{}/{}", self.save_location, &dump_id);
-    try!(fs::create_dir_all(save_location.clone()));
-    let files_in_dump = try!(self.get_files_for_dump(dump_id.clone()));
+  }
+  if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+    return Err(ErrorKind::ApiUnavailableErr(endpoint.to_owned(), status.as_u16(), body, retry_after).into());
+  }
+  Err(ErrorKind::ApiRequestErr(endpoint.to_owned(), status.as_u16(), body).into())
+}
 
-    files_in_dump.artifacts_by_table.par_iter().map(move |(_, table_artifact)| {
-      for file_to_download in table_artifact.files.iter().cloned() {
-        let finalized_to_download_path = format!("{}/{}", &save_location, &file_to_download.filename);
-        let cloned_download_path = finalized_to_download_path.clone();
-        let path = Path::new(&finalized_to_download_path);
-        if path.exists() {
-          debug!(
-            "{:?} exists, skipping entire artifact",
-            cloned_download_path
-          );
-          // Assume the entire artifact is downloaded.
-          continue;
-        } else {
-          debug!(
-            "{:?} does not exist, downloading files",
-            cloned_download_path
-          );
-          let uri = file_to_download.url.parse().expect("Failed to parse file url form hosted-data!");
-          let req = Request::new(Method::GET, uri);
-          self.client.execute(req).map(move |mut res| {
-            let download_path = cloned_download_path;
-            let mut file = File::create(Path::new(&download_path)).expect("Failed to create download file!");
-
-            res.copy_to(&mut file).expect("Failed to copy to file!")
-          }).expect("Failed to download table!");
-        }
-      }
-    }).count();
+/// Explains the likely cause of an authentication failure.
+///
+/// Requests are signed with the local time, so besides bad credentials the other common cause
+/// is the local clock drifting away from the server's clock.
+///
+/// * `server_date` - The `Date` the server sent with its response, if it sent one.
+fn get_auth_diagnostic(server_date: Option<DateTime<Utc>>) -> String {
+  let credentials = "Check that the api_key, and api_secret in canvasdataauth are correct.";
+  let server_date = match server_date {
+    Some(server_date) => server_date,
+    None => {
+      return format!(
+        "{} The server didn't send a Date, so the local clock couldn't be checked against it.",
+        credentials
+      )
+    }
+  };
 
-    trace!("Done Downloading Files for: {}", dump_id);
+  let skew = Utc::now().signed_duration_since(server_date).num_seconds();
+  if skew.abs() > MAX_CLOCK_SKEW_SECS {
+    format!(
+      "The local clock is {} seconds {} the server's clock, requests are signed with the local \
+       time so sync the local clock (e.g. with NTP). {}",
+      skew.abs(),
+      if skew > 0 { "ahead of" } else { "behind" },
+      credentials
+    )
+  } else {
+    format!(
+      "The local clock is within {} seconds of the server's clock, so it's most likely the \
+       credentials. {}",
+      MAX_CLOCK_SKEW_SECS,
+      credentials
+    )
+  }
+}
 
-    Ok(())
+/// Gets how long a response asked us to wait before retrying, in seconds.
+///
+/// Only 429, and 503 responses are expected to send a `Retry-After` header.
+///
+/// * `res` - The response to check.
+fn get_retry_after(res: &Response) -> Option<u64> {
+  let status = res.status();
+  if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+    return None;
+  }
+
+  res
+    .headers()
+    .get(RETRY_AFTER)
+    .and_then(|value| value.to_str().ok())
+    .and_then(parse_retry_after)
+}
+
+/// Parses a `Retry-After` header value into a number of seconds to wait.
+///
+/// The header can either be a number of seconds, or an HTTP Date to wait until.
+///
+/// * `value` - The value of the `Retry-After` header.
+fn parse_retry_after(value: &str) -> Option<u64> {
+  if let Ok(seconds) = value.trim().parse::<u64>() {
+    return Some(seconds);
   }
+
+  DateTime::parse_from_rfc2822(value.trim()).ok().map(|retry_at| {
+    let wait = retry_at.with_timezone(&Utc).signed_duration_since(Utc::now());
+    cmp::max(wait.num_seconds(), 0) as u64
+  })
 }
 
 /// Represents a Dump returned from the list dumps endpoint.
@@ -368,6 +1486,31 @@ pub struct FilesInDumpResponse {
 }
 unsafe impl Send for FilesInDumpResponse {}
 
+impl FilesInDumpResponse {
+  /// Gets every file in this dump across all tables.
+  pub fn get_files(&self) -> Vec<BasicFile> {
+    self
+      .artifacts_by_table
+      .values()
+      .flat_map(|table_artifact| table_artifact.files.iter().cloned())
+      .collect()
+  }
+
+  /// Determines if this dump is a historical refresh, which only has files for the requests table.
+  pub fn is_historical_refresh(&self) -> bool {
+    let mut has_found_all_requests_table = true;
+    'outer: for artifact in self.artifacts_by_table.values() {
+      for file in artifact.files.iter() {
+        if !REQREG.is_match(&file.filename) {
+          has_found_all_requests_table = false;
+          break 'outer;
+        }
+      }
+    }
+    has_found_all_requests_table
+  }
+}
+
 /// A list of artifacts per table.
 #[derive(Clone, Debug, Deserialize)]
 pub struct ArtifactByTable {
@@ -391,8 +1534,74 @@ pub struct BasicFile {
 }
 unsafe impl Send for BasicFile {}
 
-/// The Schema Definition returned by Canvas Data.
+/// The history of files for a table returned from the by table endpoint.
 #[derive(Clone, Debug, Deserialize)]
+pub struct FilesByTableResponse {
+  /// The table name this history is for.
+  pub table: String,
+  /// The files for this table in each dump.
+  pub history: Vec<TableHistoryEntry>,
+}
+unsafe impl Send for FilesByTableResponse {}
+
+/// The files for a table in a single dump.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TableHistoryEntry {
+  /// The Dump ID these files are apart of.
+  #[serde(rename = "dumpId")]
+  pub dump_id: String,
+  /// The sequence of the dump these files are apart of.
+  pub sequence: i64,
+  /// Whether or not these files are only part of the table.
+  pub partial: bool,
+  /// A List of files for this table.
+  pub files: Vec<BasicFile>,
+}
+unsafe impl Send for TableHistoryEntry {}
+
+/// The list of files returned from the sync endpoint.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SyncResponse {
+  /// The schema version these files are at.
+  #[serde(rename = "schemaVersion")]
+  pub schema_version: String,
+  /// The files that make up the current state of every table.
+  pub files: Vec<SyncFile>,
+}
+unsafe impl Send for SyncResponse {}
+
+impl SyncResponse {
+  /// Gets every file in the sync file set.
+  pub fn get_files(&self) -> Vec<BasicFile> {
+    self
+      .files
+      .iter()
+      .map(|sync_file| {
+        BasicFile {
+          url: sync_file.url.clone(),
+          filename: sync_file.filename.clone(),
+        }
+      })
+      .collect()
+  }
+}
+
+/// A File object returned from the sync endpoint.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SyncFile {
+  /// The URL for this file to download from.
+  pub url: String,
+  /// The table this file is apart of.
+  pub table: String,
+  /// The filename of this file.
+  pub filename: String,
+  /// Whether or not this file is part of a partial table.
+  pub partial: bool,
+}
+unsafe impl Send for SyncFile {}
+
+/// The Schema Definition returned by Canvas Data.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SchemaDefinition {
   /// The Version of the schema.
   pub version: String,
@@ -401,8 +1610,21 @@ pub struct SchemaDefinition {
 }
 unsafe impl Send for SchemaDefinition {}
 
+impl SchemaDefinition {
+  /// Gets the Table Definition for a Specific Table in this schema.
+  ///
+  /// * `table_name` - The Table name to get the definition for.
+  pub fn get_table_definition(&self, table_name: &str) -> Option<TableDefinition> {
+    self
+      .schema
+      .values()
+      .find(|table_def| table_def.table_name.to_lowercase() == table_name)
+      .cloned()
+  }
+}
+
 /// A Definition for a Table returned by the Schema API.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TableDefinition {
   /// The DW Type (dimension, or fact).
   pub dw_type: String,
@@ -421,7 +1643,7 @@ pub struct TableDefinition {
 unsafe impl Send for TableDefinition {}
 
 /// A Definition for a Column returned by the Schema API.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ColumnDefinition {
   /// The Type this column is.
   #[serde(rename = "type")]
@@ -438,7 +1660,7 @@ pub struct ColumnDefinition {
 unsafe impl Send for ColumnDefinition {}
 
 /// Dimension information returned by the Schema API.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct DimensionDefinition {
   /// The name of this dimension.
   pub name: String,
@@ -448,3 +1670,384 @@ pub struct DimensionDefinition {
   pub role: Option<String>,
 }
 unsafe impl Send for DimensionDefinition {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::Duration as ChronoDuration;
+  use flate2::Compression;
+  use flate2::write::GzEncoder;
+  use std::cell::{Cell, RefCell};
+  use std::env;
+  use std::process;
+  use std::time::Instant;
+
+  /// Creates an API Client from the default settings, with some settings on top.
+  ///
+  /// * `toml` - The settings to use over the defaults.
+  fn test_client(toml: &str) -> CanvasDataApiClient {
+    CanvasDataApiClient::new(&Settings::from_toml(toml)).expect("Failed to build the API Client")
+  }
+
+  #[test]
+  fn get_backoff_delay_doubles_up_to_the_max_delay_with_jitter() {
+    let api_client = test_client("[http]\nretry_base_delay_ms = 100\nretry_max_delay_ms = 1000\n");
+    // Half of each delay is random, so check the bounds enough times to see the jitter.
+    for _ in 0..50 {
+      let first = api_client.get_backoff_delay(0);
+      assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+      let fourth = api_client.get_backoff_delay(3);
+      assert!(fourth >= Duration::from_millis(400) && fourth <= Duration::from_millis(800));
+      let capped = api_client.get_backoff_delay(10);
+      assert!(capped >= Duration::from_millis(500) && capped <= Duration::from_millis(1000));
+      let overflowing = api_client.get_backoff_delay(u32::max_value());
+      assert!(overflowing >= Duration::from_millis(500) && overflowing <= Duration::from_millis(1000));
+    }
+  }
+
+  #[test]
+  fn parse_retry_after_reads_seconds() {
+    assert_eq!(parse_retry_after("120"), Some(120));
+    assert_eq!(parse_retry_after(" 5 "), Some(5));
+    assert_eq!(parse_retry_after("soon"), None);
+    assert_eq!(parse_retry_after("-1"), None);
+  }
+
+  #[test]
+  fn parse_retry_after_waits_until_an_http_date() {
+    let retry_at = (Utc::now() + ChronoDuration::seconds(60)).format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    let wait = parse_retry_after(&retry_at).expect("Failed to parse the HTTP Date");
+    assert!(wait >= 58 && wait <= 60, "waited: {}", wait);
+    // A date that's already passed means retrying right away.
+    assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0));
+  }
+
+  #[test]
+  fn with_retries_gives_up_after_max_retries() {
+    let api_client = test_client("[http]\nmax_retries = 2\nretry_base_delay_ms = 1\nretry_max_delay_ms = 1\n");
+    let attempts = Cell::new(0);
+    let result: Result<()> = api_client.with_retries("test", || {
+      attempts.set(attempts.get() + 1);
+      Err(ErrorKind::TransientHttpStatus(503, None).into())
+    });
+    assert!(result.is_err());
+    assert_eq!(attempts.get(), 3);
+  }
+
+  #[test]
+  fn with_retries_retries_until_success() {
+    let api_client = test_client("[http]\nmax_retries = 5\nretry_base_delay_ms = 1\nretry_max_delay_ms = 1\n");
+    let attempts = Cell::new(0);
+    let result = api_client.with_retries("test", || {
+      attempts.set(attempts.get() + 1);
+      if attempts.get() < 3 {
+        return Err(ErrorKind::CorruptDownload("file.gz".to_owned()).into());
+      }
+      Ok(attempts.get())
+    });
+    assert_eq!(result.ok(), Some(3));
+  }
+
+  #[test]
+  fn with_retries_doesnt_retry_other_errors() {
+    let api_client = test_client("[http]\nmax_retries = 5\nretry_base_delay_ms = 1\nretry_max_delay_ms = 1\n");
+    let attempts = Cell::new(0);
+    let result: Result<()> = api_client.with_retries("test", || {
+      attempts.set(attempts.get() + 1);
+      Err(ErrorKind::ApiRequestErr("/api/account/self/dump".to_owned(), 404, String::new()).into())
+    });
+    assert!(result.is_err());
+    assert_eq!(attempts.get(), 1);
+  }
+
+  #[test]
+  fn with_retries_caps_retry_after_at_the_max_delay() {
+    let api_client = test_client("[http]\nmax_retries = 1\nretry_base_delay_ms = 1\nretry_max_delay_ms = 10\n");
+    let started_at = Instant::now();
+    let result: Result<()> = api_client.with_retries("test", || {
+      Err(ErrorKind::TransientHttpStatus(429, Some(3600)).into())
+    });
+    assert!(result.is_err());
+    assert!(started_at.elapsed() < Duration::from_secs(5));
+  }
+
+  #[test]
+  fn get_auth_diagnostic_without_a_date_only_blames_the_credentials() {
+    let diagnostic = get_auth_diagnostic(None);
+    assert!(diagnostic.contains("didn't send a Date"), "{}", diagnostic);
+    assert!(diagnostic.contains("canvasdataauth"), "{}", diagnostic);
+  }
+
+  #[test]
+  fn get_auth_diagnostic_blames_the_credentials_inside_the_max_skew() {
+    for skew in &[-10, 0, 10] {
+      let diagnostic = get_auth_diagnostic(Some(Utc::now() + ChronoDuration::seconds(*skew)));
+      assert!(diagnostic.contains("within 60 seconds"), "{}", diagnostic);
+    }
+  }
+
+  #[test]
+  fn get_auth_diagnostic_blames_the_clock_outside_the_max_skew() {
+    let ahead = get_auth_diagnostic(Some(Utc::now() - ChronoDuration::seconds(300)));
+    assert!(ahead.contains("seconds ahead of the server's clock"), "{}", ahead);
+    assert!(ahead.contains("canvasdataauth"), "{}", ahead);
+    let behind = get_auth_diagnostic(Some(Utc::now() + ChronoDuration::seconds(300)));
+    assert!(behind.contains("seconds behind the server's clock"), "{}", behind);
+  }
+
+  /// Builds a dump as it's returned in a page of the list dumps endpoint.
+  ///
+  /// * `sequence` - The sequence number of the dump.
+  fn dump(sequence: i64) -> DumpInList {
+    serde_json::from_value(json!({
+      "dumpId": format!("dump-{}", sequence),
+      "sequence": sequence,
+      "accountId": "account",
+      "numFiles": 1,
+      "finished": true,
+      "expires": 0,
+      "updatedAt": "2026-01-01T00:00:00Z",
+      "createdAt": "2026-01-01T00:00:00Z",
+      "schemaVersion": "1.0.0",
+    })).expect("Failed to build a dump")
+  }
+
+  /// Iterates over canned pages of dumps, returning the sequence numbers of every dump, and the
+  /// `after` every page was fetched with.
+  ///
+  /// * `pages` - The pages to return, in order. Pages past the end are empty.
+  fn iterate_pages(pages: Vec<Vec<i64>>) -> (Vec<i64>, Vec<i64>) {
+    let pages = RefCell::new(VecDeque::from(pages));
+    let fetched_after = RefCell::new(Vec::new());
+    let sequences = DumpIterator::new(|after, limit| {
+      assert_eq!(limit, DUMP_PAGE_LIMIT);
+      fetched_after.borrow_mut().push(after);
+      let page = pages.borrow_mut().pop_front().unwrap_or(Vec::new());
+      Ok(page.into_iter().map(dump).collect())
+    }).map(|dump| dump.expect("Failed to get a dump").sequence)
+      .collect();
+    (sequences, fetched_after.into_inner())
+  }
+
+  #[test]
+  fn build_signed_request_sorts_the_query_string_and_signs_the_port() {
+    let api_client = test_client("api_url = \"http://localhost:8080/\"\n");
+    let req = api_client
+      .build_signed_request("/api/account/self/dump", &[("limit", "100".to_owned()), ("after", "5".to_owned())])
+      .expect("Failed to build the request");
+    assert_eq!(req.url().as_str(), "http://localhost:8080/api/account/self/dump?after=5&limit=100");
+
+    let date = req.headers()[DATE].to_str().expect("Date isn't a string").to_owned();
+    let expected = api_client.compute_auth_header(
+      "GET",
+      "localhost:8080",
+      "application/json",
+      "",
+      "/api/account/self/dump",
+      "after=5&limit=100",
+      &date,
+    );
+    assert_eq!(req.headers()["Authorization"].to_str().ok(), Some(expected.as_str()));
+  }
+
+  #[test]
+  fn build_signed_request_leaves_out_the_default_port() {
+    let api_client = test_client("api_url = \"https://portal.inshosteddata.com:443\"\n");
+    let req = api_client.build_signed_request("/api/schema/latest", &[]).expect("Failed to build the request");
+    assert_eq!(req.url().as_str(), "https://portal.inshosteddata.com/api/schema/latest");
+
+    let date = req.headers()[DATE].to_str().expect("Date isn't a string").to_owned();
+    let expected = api_client.compute_auth_header(
+      "GET",
+      "portal.inshosteddata.com",
+      "application/json",
+      "",
+      "/api/schema/latest",
+      "",
+      &date,
+    );
+    assert_eq!(req.headers()["Authorization"].to_str().ok(), Some(expected.as_str()));
+  }
+
+  #[test]
+  fn dump_iterator_keeps_paging_past_short_pages() {
+    let (sequences, fetched_after) = iterate_pages(vec![vec![1, 2], vec![3], vec![4, 5]]);
+    assert_eq!(sequences, vec![1, 2, 3, 4, 5]);
+    assert_eq!(fetched_after, vec![0, 2, 3, 5]);
+  }
+
+  #[test]
+  fn dump_iterator_stops_on_an_empty_page() {
+    let (sequences, fetched_after) = iterate_pages(vec![]);
+    assert!(sequences.is_empty());
+    assert_eq!(fetched_after, vec![0]);
+  }
+
+  #[test]
+  fn dump_iterator_stops_when_a_page_doesnt_move_forward() {
+    let (sequences, fetched_after) = iterate_pages(vec![vec![1, 2], vec![2], vec![3]]);
+    assert_eq!(sequences, vec![1, 2, 2]);
+    assert_eq!(fetched_after, vec![0, 2]);
+  }
+
+  #[test]
+  fn dump_iterator_stops_after_an_error() {
+    let fetches = Cell::new(0);
+    let results: Vec<_> = DumpIterator::new(|_, _| {
+      fetches.set(fetches.get() + 1);
+      Err(ErrorKind::ApiRequestErr("/api/account/self/dump".to_owned(), 500, String::new()).into())
+    }).collect();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_err());
+    assert_eq!(fetches.get(), 1);
+  }
+
+  /// Creates an empty directory for a test to write files in.
+  ///
+  /// * `name` - The name of the test.
+  fn test_directory(name: &str) -> PathBuf {
+    let directory = env::temp_dir().join(format!("cdl-api-client-test-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).expect("Failed to create the test directory");
+    directory
+  }
+
+  /// Compresses some data into a gzip file.
+  ///
+  /// * `data` - The data to compress.
+  fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).expect("Failed to compress the data");
+    encoder.finish().expect("Failed to compress the data")
+  }
+
+  #[test]
+  fn parse_content_range_reads_the_start_and_total() {
+    assert_eq!(parse_content_range("bytes 100-199/200"), Some((100, Some(200))));
+    assert_eq!(parse_content_range(" bytes 0-99/100 "), Some((0, Some(100))));
+  }
+
+  #[test]
+  fn parse_content_range_allows_an_unknown_total() {
+    assert_eq!(parse_content_range("bytes 100-199/*"), Some((100, None)));
+    assert_eq!(parse_content_range("bytes 100-199"), Some((100, None)));
+  }
+
+  #[test]
+  fn parse_content_range_has_no_start_for_an_unsatisfied_range() {
+    assert_eq!(parse_content_range("bytes */200"), None);
+  }
+
+  #[test]
+  fn parse_content_range_refuses_malformed_values() {
+    assert_eq!(parse_content_range(""), None);
+    assert_eq!(parse_content_range("bytes"), None);
+    assert_eq!(parse_content_range("bytes abc-def/200"), None);
+    assert_eq!(parse_content_range("items 1-2/3"), None);
+  }
+
+  #[test]
+  fn check_part_file_keeps_a_short_file_to_resume() {
+    let directory = test_directory("short-part");
+    let part_path = directory.join("file.gz.part");
+    let data = gzip(b"1\t2\n");
+    fs::write(&part_path, &data[..data.len() - 4]).expect("Failed to write the part file");
+    let part_path = part_path.to_string_lossy().into_owned();
+
+    match check_part_file("file.gz", &part_path, Some(data.len() as u64)) {
+      Err(Error(ErrorKind::IncompleteDownload(_, actual_size, expected_size), _)) => {
+        assert_eq!(actual_size, data.len() as u64 - 4);
+        assert_eq!(expected_size, data.len() as u64);
+      }
+      other => panic!("Expected an incomplete download, got: {:?}", other.map_err(|err| err.to_string())),
+    }
+    assert!(Path::new(&part_path).exists());
+    let _ = fs::remove_dir_all(&directory);
+  }
+
+  #[test]
+  fn check_part_file_removes_a_corrupt_file() {
+    let directory = test_directory("corrupt-part");
+    let part_path = directory.join("file.gz.part");
+    fs::write(&part_path, b"not gzip").expect("Failed to write the part file");
+    let part_path = part_path.to_string_lossy().into_owned();
+
+    match check_part_file("file.gz", &part_path, Some(8)) {
+      Err(Error(ErrorKind::CorruptDownload(_), _)) => {}
+      other => panic!("Expected a corrupt download, got: {:?}", other.map_err(|err| err.to_string())),
+    }
+    assert!(!Path::new(&part_path).exists());
+    let _ = fs::remove_dir_all(&directory);
+  }
+
+  #[test]
+  fn check_part_file_accepts_a_complete_file() {
+    let directory = test_directory("complete-part");
+    let part_path = directory.join("file.gz.part");
+    let data = gzip(b"1\t2\n");
+    fs::write(&part_path, &data).expect("Failed to write the part file");
+    let part_path = part_path.to_string_lossy().into_owned();
+
+    assert!(check_part_file("file.gz", &part_path, Some(data.len() as u64)).is_ok());
+    assert!(check_part_file("file.gz", &part_path, None).is_ok());
+    let _ = fs::remove_dir_all(&directory);
+  }
+
+  #[test]
+  fn is_verified_download_checks_and_records_a_new_file() {
+    let directory = test_directory("new-download");
+    let path = directory.join("file.gz");
+    fs::write(&path, gzip(b"1\t2\n")).expect("Failed to write the file");
+    let path = path.to_string_lossy().into_owned();
+    let record_path = format!("{}{}", path, VERIFIED_FILE_SUFFIX);
+
+    assert!(!Path::new(&record_path).exists());
+    assert!(is_verified_download(&path));
+    assert_eq!(
+      fs::read_to_string(&record_path).expect("Failed to read the record"),
+      get_verified_record(&path).expect("Failed to get the record")
+    );
+    let _ = fs::remove_dir_all(&directory);
+  }
+
+  #[test]
+  fn is_verified_download_trusts_a_matching_record() {
+    let directory = test_directory("trusted-download");
+    let path = directory.join("file.gz");
+    // Not gzip, so only the record can make this count as verified.
+    fs::write(&path, b"not gzip").expect("Failed to write the file");
+    let path = path.to_string_lossy().into_owned();
+
+    assert!(!is_verified_download(&path));
+    record_verified_download(&path);
+    assert!(is_verified_download(&path));
+    let _ = fs::remove_dir_all(&directory);
+  }
+
+  #[test]
+  fn is_verified_download_rechecks_a_file_with_a_stale_record() {
+    let directory = test_directory("stale-download");
+    let path = directory.join("file.gz");
+    fs::write(&path, gzip(b"1\t2\n")).expect("Failed to write the file");
+    let path = path.to_string_lossy().into_owned();
+    assert!(is_verified_download(&path));
+
+    // The file is replaced after it was verified, so its record no longer matches.
+    fs::write(&path, b"a longer file that isn't gzip").expect("Failed to replace the file");
+    assert!(!is_verified_download(&path));
+    let _ = fs::remove_dir_all(&directory);
+  }
+
+  #[test]
+  fn is_verified_download_ignores_a_record_of_another_size() {
+    let directory = test_directory("mismatched-download");
+    let path = directory.join("file.gz");
+    fs::write(&path, b"not gzip").expect("Failed to write the file");
+    let path = path.to_string_lossy().into_owned();
+    fs::write(format!("{}{}", path, VERIFIED_FILE_SUFFIX), "1 0.000000000").expect("Failed to write the record");
+
+    assert!(!is_verified_download(&path));
+    assert!(!is_verified_download(&directory.join("missing.gz").to_string_lossy()));
+    let _ = fs::remove_dir_all(&directory);
+  }
+}
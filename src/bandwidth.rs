@@ -0,0 +1,96 @@
+//! Limits how fast files are downloaded, shared across every download at once.
+
+use std::cmp;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A Bandwidth Limiter shared across every download.
+///
+/// Every chunk that gets downloaded reserves the time it takes to transfer it at the limit, and
+/// waits until its reservation starts. So all downloads together stay under the limit.
+pub struct BandwidthLimiter {
+  /// The maximum number of bytes per second.
+  bytes_per_second: u64,
+  /// When the next chunk is allowed to be transferred.
+  next_available: Mutex<Instant>,
+}
+
+impl BandwidthLimiter {
+  /// Creates a new Bandwidth Limiter.
+  ///
+  /// * `bytes_per_second` - The maximum number of bytes per second, across every download.
+  pub fn new(bytes_per_second: u64) -> Self {
+    BandwidthLimiter {
+      bytes_per_second: cmp::max(bytes_per_second, 1),
+      next_available: Mutex::new(Instant::now()),
+    }
+  }
+
+  /// Gets how long it takes to transfer a number of bytes at the limit.
+  ///
+  /// * `bytes` - The number of bytes.
+  fn get_transfer_time(&self, bytes: u64) -> Duration {
+    let secs = bytes / self.bytes_per_second;
+    let nanos = (bytes % self.bytes_per_second) * 1_000_000_000 / self.bytes_per_second;
+    Duration::new(secs, nanos as u32)
+  }
+
+  /// Waits until a chunk of bytes is allowed to be transferred.
+  ///
+  /// * `bytes` - The size of the chunk.
+  pub fn consume(&self, bytes: u64) {
+    let wait = {
+      let mut next_available = self.next_available.lock().expect("Bandwidth limiter lock was poisoned!");
+      let now = Instant::now();
+      let start = cmp::max(*next_available, now);
+      *next_available = start + self.get_transfer_time(bytes);
+      start - now
+    };
+
+    if wait > Duration::from_millis(0) {
+      thread::sleep(wait);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use settings::Settings;
+
+  #[test]
+  fn get_transfer_time_is_the_time_at_the_limit() {
+    let bandwidth_limiter = BandwidthLimiter::new(1000);
+    assert_eq!(bandwidth_limiter.get_transfer_time(1500), Duration::from_millis(1500));
+    assert_eq!(bandwidth_limiter.get_transfer_time(0), Duration::from_millis(0));
+    assert_eq!(BandwidthLimiter::new(3).get_transfer_time(1), Duration::new(0, 333_333_333));
+  }
+
+  #[test]
+  fn consume_waits_for_the_bytes_before_it() {
+    let bandwidth_limiter = BandwidthLimiter::new(1000);
+    let started_at = Instant::now();
+    // The first chunk goes right away, and each one after waits for the ones before it.
+    bandwidth_limiter.consume(100);
+    assert!(started_at.elapsed() < Duration::from_millis(100));
+    bandwidth_limiter.consume(100);
+    bandwidth_limiter.consume(100);
+    let elapsed = started_at.elapsed();
+    assert!(elapsed >= Duration::from_millis(200), "took: {:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "took: {:?}", elapsed);
+  }
+
+  #[test]
+  fn downloads_are_unlimited_without_a_limit() {
+    assert_eq!(Settings::from_toml("").get_max_download_bytes_per_second(), None);
+    assert_eq!(
+      Settings::from_toml("[http]\nmax_download_bytes_per_second = 0\n").get_max_download_bytes_per_second(),
+      None
+    );
+    assert_eq!(
+      Settings::from_toml("[http]\nmax_download_bytes_per_second = 1024\n").get_max_download_bytes_per_second(),
+      Some(1024)
+    );
+  }
+}
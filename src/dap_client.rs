@@ -0,0 +1,577 @@
+//! Provides an API Client for the Canvas Data 2 (DAP) API.
+//!
+//! Unlike the Canvas Data API, DAP authenticates with OAuth client credentials, and instead of
+//! dumps it serves per table queries. A query is either a snapshot of the whole table, or the
+//! changes to the table since a timestamp. Queries run as jobs, and once a job completes its
+//! results are downloaded as gzipped JSONL files.
+
+use api_client::{build_http_client, BasicFile, CanvasDataApiClient};
+use errors::*;
+use reqwest::{Client as HttpClient, RequestBuilder, Response, StatusCode};
+use reqwest::header::AUTHORIZATION;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use settings::Settings;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The directory under the save location that DAP query results are saved to.
+pub const DAP_DIRECTORY: &'static str = "dap";
+
+/// How long before an access token expires to fetch a new one, in seconds.
+const ACCESS_TOKEN_EXPIRY_MARGIN_SECS: u64 = 60;
+
+/// The API Client for Canvas Data 2 (DAP).
+#[derive(Clone)]
+pub struct DapClient {
+  /// The OAuth Client ID.
+  client_id: String,
+  /// The OAuth Client Secret.
+  client_secret: String,
+  /// The Base URL (scheme, host, and port) of the DAP API.
+  api_url: String,
+  /// The namespace of the tables to query.
+  namespace: String,
+  /// The place to save files.
+  save_location: String,
+  /// How long to wait between checks on a query job.
+  poll_interval: Duration,
+  /// The current access token, once we've fetched one.
+  access_token: Arc<RwLock<Option<AccessToken>>>,
+  /// The Reqwest Client.
+  client: HttpClient,
+  /// The Canvas Data API Client, whose retries, and downloads DAP shares.
+  downloader: CanvasDataApiClient,
+}
+
+/// An OAuth access token, and when it expires.
+struct AccessToken {
+  /// The token to send as a bearer token.
+  token: String,
+  /// When we should stop using the token.
+  expires_at: Instant,
+}
+
+impl DapClient {
+  /// Creates a new DAP API Client.
+  ///
+  /// * `settings` - The settings to use for this API Client.
+  pub fn new(settings: &Settings) -> Result<Self> {
+    let (client_id, client_secret) = match settings.get_dap_client_credentials() {
+      Some(credentials) => credentials,
+      None => return Err(ErrorKind::DapNotConfigured.into()),
+    };
+
+    Ok(DapClient {
+      client_id: client_id,
+      client_secret: client_secret,
+      api_url: settings.get_dap_api_url().trim_right_matches('/').to_owned(),
+      namespace: settings.get_dap_namespace(),
+      save_location: settings.get_save_location(),
+      poll_interval: Duration::from_secs(settings.get_dap_poll_interval_secs()),
+      access_token: Arc::new(RwLock::new(None)),
+      client: try!(build_http_client(settings)),
+      // Every query names its result files the same way, so they can't be cached by name.
+      downloader: try!(CanvasDataApiClient::new(settings)).without_download_cache(),
+    })
+  }
+
+  /// Gets an access token, fetching a new one if we don't have one, or it's about to expire.
+  fn get_access_token(&self) -> Result<String> {
+    if let Some(ref access_token) = *self.access_token.read().expect("Access token lock was poisoned!") {
+      if access_token.expires_at > Instant::now() {
+        return Ok(access_token.token.clone());
+      }
+    }
+
+    trace!("Fetching a new DAP access token");
+    let endpoint = "/ids/auth/login";
+    let mut res = try!(
+      self
+        .client
+        .post(&format!("{}{}", self.api_url, endpoint))
+        .basic_auth(self.client_id.clone(), Some(self.client_secret.clone()))
+        .form(&[("grant_type", "client_credentials")])
+        .send()
+    );
+    try!(check_dap_status(endpoint, &mut res));
+    let token_response: AccessTokenResponse = try!(res.json());
+
+    let lifetime = token_response.expires_in.saturating_sub(ACCESS_TOKEN_EXPIRY_MARGIN_SECS);
+    *self.access_token.write().expect("Access token lock was poisoned!") = Some(AccessToken {
+      token: token_response.access_token.clone(),
+      expires_at: Instant::now() + Duration::from_secs(lifetime),
+    });
+    Ok(token_response.access_token)
+  }
+
+  /// Sends an authenticated request to the DAP API, and parses the JSON response.
+  ///
+  /// The request is retried with a backoff on transient failures, the same as Canvas Data API
+  /// requests are.
+  ///
+  /// * `endpoint` - The path of the API endpoint, used for errors.
+  /// * `request` - Builds the request to send, without authentication. It's called once per attempt.
+  fn send_json<T: DeserializeOwned>(&self, endpoint: &str, request: &Fn() -> RequestBuilder) -> Result<T> {
+    self.downloader.with_retries(endpoint, || {
+      let access_token = try!(self.get_access_token());
+      let mut res = try!(
+        request()
+          .header(AUTHORIZATION, format!("Bearer {}", access_token))
+          .send()
+      );
+      try!(check_dap_status(endpoint, &mut res));
+      Ok(try!(res.json()))
+    })
+  }
+
+  /// Performs an authenticated GET against the DAP API.
+  ///
+  /// * `path` - The path of the API endpoint to request.
+  fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+    self.send_json(path, &|| self.client.get(&format!("{}{}", self.api_url, path)))
+  }
+
+  /// Performs an authenticated POST of a JSON body against the DAP API.
+  ///
+  /// * `path` - The path of the API endpoint to request.
+  /// * `body` - The JSON body to send.
+  fn post_json<T: DeserializeOwned>(&self, path: &str, body: &Value) -> Result<T> {
+    self.send_json(path, &|| self.client.post(&format!("{}{}", self.api_url, path)).json(body))
+  }
+
+  /// Lists every table in the namespace.
+  pub fn list_tables(&self) -> Result<Vec<String>> {
+    trace!("List tables was called for namespace: [ {} ]", self.namespace);
+
+    let tables: TablesResponse = try!(self.get_json(&format!("/dap/query/{}/table", self.namespace)));
+    Ok(tables.tables)
+  }
+
+  /// Gets the current schema of a table.
+  ///
+  /// * `table_name` - The table to get the schema of.
+  pub fn get_table_schema(&self, table_name: &str) -> Result<DapTableSchema> {
+    trace!("Get table schema was called for: [ {} ]", table_name);
+
+    self.get_json(&format!("/dap/query/{}/table/{}/schema", self.namespace, table_name))
+  }
+
+  /// Queries a table, waits for the query to finish, and downloads its results.
+  ///
+  /// Without a `since` timestamp the query is a snapshot of the whole table, otherwise it's
+  /// every change to the table since that timestamp. Any results previously downloaded for the
+  /// table are removed first.
+  ///
+  /// * `table_name` - The table to query.
+  /// * `since` - The timestamp of the last sync, for an incremental query.
+  pub fn query_table(&self, table_name: &str, since: Option<&str>) -> Result<DapQueryResult> {
+    trace!("Query table was called for: [ {} ] since: [ {:?} ]", table_name, since);
+
+    let mut query = json!({ "format": "jsonl" });
+    if let Some(since) = since {
+      query["since"] = Value::String(since.to_owned());
+    }
+    let job: DapJob = try!(self.post_json(
+      &format!("/dap/query/{}/table/{}/data", self.namespace, table_name),
+      &query,
+    ));
+    let job = try!(self.wait_for_job(job));
+
+    let timestamp = match job.until.clone().or(job.at.clone()) {
+      Some(timestamp) => timestamp,
+      None => return Err(ErrorKind::DapJobFailed(job.id, "complete without a timestamp".to_owned()).into()),
+    };
+    let files = try!(self.download_job_objects(table_name, &job));
+    Ok(DapQueryResult {
+      is_incremental: since.is_some(),
+      schema_version: job.schema_version.unwrap_or(0),
+      timestamp: timestamp,
+      files: files,
+    })
+  }
+
+  /// Waits for a query job to complete.
+  ///
+  /// * `job` - The job to wait for.
+  fn wait_for_job(&self, mut job: DapJob) -> Result<DapJob> {
+    loop {
+      match job.status.as_str() {
+        "complete" => return Ok(job),
+        "waiting" | "running" => {
+          debug!("Waiting for DAP job: {} which is: {}", job.id, job.status);
+          thread::sleep(self.poll_interval);
+          job = try!(self.get_json(&format!("/dap/job/{}", job.id)));
+        }
+        _ => return Err(ErrorKind::DapJobFailed(job.id.clone(), job.status.clone()).into()),
+      }
+    }
+  }
+
+  /// Gets the result objects of a completed job as files with freshly pre-signed URLs.
+  ///
+  /// * `job` - The completed job.
+  fn get_job_files(&self, job: &DapJob) -> Result<Vec<BasicFile>> {
+    let object_ids: Vec<Value> = job.objects.iter().map(|object| json!({ "id": object.id })).collect();
+    let object_urls: ObjectUrlsResponse = try!(self.post_json("/dap/object/url", &Value::Array(object_ids)));
+
+    let mut files = Vec::new();
+    for (pos, object) in job.objects.iter().enumerate() {
+      let url = match object_urls.urls.get(&object.id) {
+        Some(object_url) => object_url.url.clone(),
+        None => return Err(ErrorKind::DapJobFailed(job.id.clone(), format!("no URL for object {}", object.id)).into()),
+      };
+      files.push(BasicFile {
+        url: url,
+        filename: format!("part-{:05}.jsonl.gz", pos),
+      });
+    }
+    Ok(files)
+  }
+
+  /// Downloads the result objects of a completed job into the table's directory.
+  ///
+  /// Objects are downloaded the same way as the files of a dump: on the download pool, through
+  /// `.part` files, with retries, and validation. Object URLs are pre-signed, so they don't take
+  /// the access token, and are signed again if they expire.
+  ///
+  /// * `table_name` - The table the job queried.
+  /// * `job` - The completed job.
+  fn download_job_objects(&self, table_name: &str, job: &DapJob) -> Result<Vec<PathBuf>> {
+    let table_directory = Path::new(&self.save_location).join(DAP_DIRECTORY).join(table_name);
+    if table_directory.exists() {
+      try!(fs::remove_dir_all(&table_directory));
+    }
+    try!(fs::create_dir_all(&table_directory));
+
+    if job.objects.is_empty() {
+      return Ok(Vec::new());
+    }
+    let files = try!(self.get_job_files(job));
+    debug!("Downloading {} DAP objects to: {:?}", files.len(), table_directory);
+    try!(self.downloader.download_files(
+      &table_directory.to_string_lossy(),
+      &files,
+      &|| self.get_job_files(job),
+    ));
+
+    Ok(files.iter().map(|file| table_directory.join(&file.filename)).collect())
+  }
+}
+
+/// Checks the HTTP Status of a DAP API response, turning failures into typed errors.
+///
+/// * `endpoint` - The API endpoint that was requested.
+/// * `res` - The response to check.
+fn check_dap_status(endpoint: &str, res: &mut Response) -> Result<()> {
+  let status = res.status();
+  if status.is_success() {
+    return Ok(());
+  }
+
+  let body = res.text().unwrap_or(String::new());
+  if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+    return Err(
+      ErrorKind::ApiAuthErr(
+        endpoint.to_owned(),
+        status.as_u16(),
+        body,
+        "Check that the client_id, and client_secret in dap are correct.".to_owned(),
+      ).into(),
+    );
+  }
+  if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+    return Err(ErrorKind::ApiUnavailableErr(endpoint.to_owned(), status.as_u16(), body, None).into());
+  }
+  Err(ErrorKind::ApiRequestErr(endpoint.to_owned(), status.as_u16(), body).into())
+}
+
+/// The response from the OAuth login endpoint.
+#[derive(Clone, Debug, Deserialize)]
+struct AccessTokenResponse {
+  /// The access token.
+  access_token: String,
+  /// How long the access token is valid for in seconds.
+  expires_in: u64,
+}
+
+/// The response from the list tables endpoint.
+#[derive(Clone, Debug, Deserialize)]
+struct TablesResponse {
+  /// The names of the tables in the namespace.
+  tables: Vec<String>,
+}
+
+/// The current schema of a DAP table.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DapTableSchema {
+  /// The version of the table's schema.
+  pub version: i64,
+  /// The JSON Schema of a record in the table, with `key`, `value`, and `meta` properties.
+  pub schema: Value,
+}
+
+impl DapTableSchema {
+  /// Gets the columns of the table from the `key`, and `value` properties of its JSON Schema.
+  ///
+  /// Returns the (<Key Column Names>, <Column Name, Canvas Data Column Type>) items. The types
+  /// are the same names the Canvas Data Schema uses, so they can go through the type converter.
+  pub fn get_columns(&self) -> (Vec<String>, BTreeMap<String, String>) {
+    let mut key_columns = Vec::new();
+    let mut columns = BTreeMap::new();
+
+    for (section, is_key) in vec![("key", true), ("value", false)] {
+      let properties = self
+        .resolve(&self.schema["properties"][section])
+        .get("properties")
+        .and_then(|properties| properties.as_object())
+        .cloned()
+        .unwrap_or_default();
+      for (name, property) in properties.iter() {
+        if is_key {
+          key_columns.push(name.clone());
+        }
+        columns.insert(name.clone(), self.get_column_type(property));
+      }
+    }
+
+    (key_columns, columns)
+  }
+
+  /// Follows a `$ref` to the definition it points at in the schema.
+  ///
+  /// * `property` - The property to resolve.
+  fn resolve<'a>(&'a self, property: &'a Value) -> &'a Value {
+    match property.get("$ref").and_then(|reference| reference.as_str()) {
+      Some(reference) if reference.starts_with("#/") => {
+        let pointer = &reference[1..];
+        self.schema.pointer(pointer).unwrap_or(property)
+      }
+      _ => property,
+    }
+  }
+
+  /// Gets the Canvas Data type of a property in the JSON Schema.
+  ///
+  /// Objects, arrays, and anything else we can't map are kept as JSON text.
+  ///
+  /// * `property` - The property to get the type of.
+  fn get_column_type(&self, property: &Value) -> String {
+    let property = self.resolve(property);
+    let format = property.get("format").and_then(|format| format.as_str()).unwrap_or("");
+    let column_type = match property.get("type").and_then(|the_type| the_type.as_str()) {
+      Some("integer") if format == "int32" => "int",
+      Some("integer") => "bigint",
+      Some("number") => "double precision",
+      Some("boolean") => "boolean",
+      Some("string") if format == "date-time" => "timestamp",
+      Some("string") if format == "date" => "date",
+      _ => "text",
+    };
+    column_type.to_owned()
+  }
+}
+
+/// A DAP query job.
+#[derive(Clone, Debug, Deserialize)]
+struct DapJob {
+  /// The ID of the job.
+  id: String,
+  /// The status of the job, one of: waiting, running, complete, or failed.
+  status: String,
+  /// The result objects of the job, once it's complete.
+  #[serde(default)]
+  objects: Vec<DapObject>,
+  /// The schema version of the results, once it's complete.
+  schema_version: Option<i64>,
+  /// The timestamp a snapshot was taken at.
+  at: Option<String>,
+  /// The timestamp an incremental query has changes up until.
+  until: Option<String>,
+}
+
+/// A result object of a DAP query job.
+#[derive(Clone, Debug, Deserialize)]
+struct DapObject {
+  /// The ID of the object.
+  id: String,
+}
+
+/// The response from the object URL endpoint.
+#[derive(Clone, Debug, Deserialize)]
+struct ObjectUrlsResponse {
+  /// The pre-signed URLs, keyed by object ID.
+  urls: BTreeMap<String, ObjectUrl>,
+}
+
+/// A pre-signed URL for a result object.
+#[derive(Clone, Debug, Deserialize)]
+struct ObjectUrl {
+  /// The URL to download the object from.
+  url: String,
+}
+
+/// The downloaded results of a DAP query.
+#[derive(Clone, Debug)]
+pub struct DapQueryResult {
+  /// Whether the results are the changes since a timestamp, rather than a snapshot.
+  pub is_incremental: bool,
+  /// The schema version of the results.
+  pub schema_version: i64,
+  /// The timestamp to query changes since on the next sync.
+  pub timestamp: String,
+  /// The downloaded gzipped JSONL files.
+  pub files: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use flate2::Compression;
+  use flate2::read::GzDecoder;
+  use flate2::write::GzEncoder;
+  use std::env;
+  use std::io::{BufRead, BufReader, Read, Write};
+  use std::net::TcpListener;
+  use std::process;
+  use std::thread::JoinHandle;
+
+  /// A canned response from the stub DAP server.
+  struct StubResponse {
+    /// The start of the request line this answers, e.g. `GET /dap/job/1`.
+    request: String,
+    /// The HTTP Status line, e.g. `200 OK`.
+    status: &'static str,
+    /// The body of the response.
+    body: Vec<u8>,
+  }
+
+  impl StubResponse {
+    /// Creates a canned response.
+    ///
+    /// * `request` - The start of the request line this answers.
+    /// * `status` - The HTTP Status line.
+    /// * `body` - The body of the response.
+    fn new(request: &str, status: &'static str, body: Vec<u8>) -> Self {
+      StubResponse {
+        request: request.to_owned(),
+        status: status,
+        body: body,
+      }
+    }
+  }
+
+  /// Answers one request per connection with the first unused canned response for it, until every
+  /// response is used.
+  ///
+  /// * `listener` - The listener the stub server was bound to.
+  /// * `responses` - The canned responses, in the order they're used.
+  fn serve_stub_responses(listener: TcpListener, mut responses: Vec<StubResponse>) -> JoinHandle<Vec<String>> {
+    thread::spawn(move || {
+      let mut requests = Vec::new();
+      while !responses.is_empty() {
+        let (stream, _) = listener.accept().expect("Failed to accept a connection");
+        let mut reader = BufReader::new(stream.try_clone().expect("Failed to clone the connection"));
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).expect("Failed to read the request line");
+        let mut content_length = 0;
+        loop {
+          let mut header = String::new();
+          reader.read_line(&mut header).expect("Failed to read a header");
+          if header.trim().is_empty() {
+            break;
+          }
+          let lowercase_header = header.to_lowercase();
+          if lowercase_header.starts_with("content-length:") {
+            content_length = lowercase_header["content-length:".len()..].trim().parse().unwrap_or(0);
+          }
+        }
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body).expect("Failed to read the request body");
+
+        let (status, body) = match responses.iter().position(|res| request_line.starts_with(&res.request)) {
+          Some(pos) => {
+            let response = responses.remove(pos);
+            (response.status, response.body)
+          }
+          None => ("404 Not Found", Vec::new()),
+        };
+        let mut stream = stream;
+        write!(
+          stream,
+          "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n",
+          status,
+          body.len()
+        ).expect("Failed to write the response headers");
+        stream.write_all(&body).expect("Failed to write the response body");
+        requests.push(request_line.trim().to_owned());
+      }
+      requests
+    })
+  }
+
+  #[test]
+  fn query_table_retries_downloads_into_validated_files() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind the stub server");
+    let api_url = format!("http://{}", listener.local_addr().expect("Stub server has no address"));
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"{\"key\":{\"id\":1}}\n").expect("Failed to compress the object");
+    let object = encoder.finish().expect("Failed to compress the object");
+
+    let responses = vec![
+      StubResponse::new(
+        "POST /ids/auth/login ",
+        "200 OK",
+        br#"{"access_token":"token","expires_in":3600}"#.to_vec(),
+      ),
+      StubResponse::new(
+        "POST /dap/query/canvas/table/accounts/data ",
+        "200 OK",
+        br#"{"id":"job","status":"complete","objects":[{"id":"object"}],"schema_version":2,"at":"2026-01-01"}"#
+          .to_vec(),
+      ),
+      StubResponse::new(
+        "POST /dap/object/url ",
+        "200 OK",
+        format!(r#"{{"urls":{{"object":{{"url":"{}/objects/object"}}}}}}"#, api_url).into_bytes(),
+      ),
+      // The first download fails, and has to be retried.
+      StubResponse::new("GET /objects/object ", "503 Service Unavailable", Vec::new()),
+      StubResponse::new("GET /objects/object ", "200 OK", object),
+    ];
+    let server = serve_stub_responses(listener, responses);
+
+    let save_location = env::temp_dir().join(format!("cdl-dap-client-test-{}", process::id()));
+    let settings = Settings::from_toml(&format!(
+      "save_location = {:?}\n\
+       [http]\nretry_base_delay_ms = 1\n\
+       [dap]\nclient_id = \"client_id\"\nclient_secret = \"client_secret\"\napi_url = {:?}\npoll_interval_secs = 0\n",
+      save_location.to_string_lossy(),
+      api_url
+    ));
+    let dap_client = DapClient::new(&settings).expect("Failed to build the DAP Client");
+
+    let result = dap_client.query_table("accounts", None).expect("Failed to query the table");
+    let requests = server.join().expect("The stub server panicked");
+    assert_eq!(requests.iter().filter(|request| request.starts_with("GET /objects/object ")).count(), 2);
+
+    assert!(!result.is_incremental);
+    assert_eq!(result.schema_version, 2);
+    assert_eq!(result.timestamp, "2026-01-01");
+    assert_eq!(result.files.len(), 1);
+    assert!(!Path::new(&format!("{}.part", result.files[0].to_string_lossy())).exists());
+    let mut contents = String::new();
+    GzDecoder::new(fs::File::open(&result.files[0]).expect("Failed to open the object"))
+      .read_to_string(&mut contents)
+      .expect("Failed to decompress the object");
+    assert_eq!(contents, "{\"key\":{\"id\":1}}\n");
+
+    let _ = fs::remove_dir_all(&save_location);
+  }
+}
@@ -0,0 +1,371 @@
+//! Imports the results of Canvas Data 2 (DAP) queries into a database.
+
+use dap_client::{DapClient, DapQueryResult, DapTableSchema};
+use db_client::ImportDatabaseAdapter;
+use errors::*;
+use flate2::read::GzDecoder;
+use importer::forget_table_hashes;
+use rocksdb::DB;
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor};
+use std::path::Path;
+use std::sync::Arc;
+use type_converter::convert_type_for_db;
+
+/// Syncs DAP tables into a database.
+pub struct DapImporter<T: ImportDatabaseAdapter> {
+  /// The DAP API Client.
+  dap_client: DapClient,
+  /// The Importing Database Adapter.
+  db_adapter: T,
+  /// The state store, used to forget about tables that a sync replaces.
+  whiskey: Option<Arc<DB>>,
+}
+
+impl<T: ImportDatabaseAdapter> DapImporter<T> {
+  /// Creates a new DAP Importer.
+  ///
+  /// * `dap_client` - The DAP API Client to use.
+  /// * `db_adapter` - The Database Adapter to Import Into.
+  pub fn new(dap_client: DapClient, db_adapter: T) -> Self {
+    DapImporter {
+      dap_client: dap_client,
+      db_adapter: db_adapter,
+      whiskey: None,
+    }
+  }
+
+  /// Sets the state store, so tables synced from DAP are never skipped by a dump import as unchanged.
+  ///
+  /// * `whiskey` - The state store.
+  pub fn set_state_store(&mut self, whiskey: Arc<DB>) {
+    self.whiskey = Some(whiskey);
+  }
+
+  /// Syncs a single table.
+  ///
+  /// Without a `since` timestamp the table is dropped, and reloaded from a snapshot, which is bulk
+  /// loaded when the database supports it. Otherwise only the changes since the timestamp are
+  /// applied. Either way the changes are only seen once every file is imported. Returns the
+  /// results of the query, so the caller can record the timestamp to sync from next time.
+  ///
+  /// * `table_name` - The table to sync.
+  /// * `schema` - The current schema of the table.
+  /// * `since` - The timestamp of the last sync of the table.
+  pub fn sync_table(&self, table_name: &str, schema: &DapTableSchema, since: Option<&str>) -> Result<DapQueryResult> {
+    trace!("Sync Table Called for: {} since: {:?}", table_name, since);
+
+    let (key_columns, columns) = schema.get_columns();
+    if key_columns.len() != 1 {
+      return Err(ErrorKind::DapUnsupportedKey(table_name.to_owned(), key_columns).into());
+    }
+    let key_column = key_columns[0].clone();
+    let mut column_types = BTreeMap::new();
+    for (column_name, column_type) in columns.into_iter() {
+      column_types.insert(
+        column_name,
+        try!(convert_type_for_db(column_type, self.db_adapter.get_db_type())),
+      );
+    }
+
+    let result = try!(self.dap_client.query_table(table_name, since));
+    info!(
+      "Importing {} DAP files for table: {} ({})",
+      result.files.len(),
+      table_name,
+      if result.is_incremental { "incremental" } else { "snapshot" }
+    );
+
+    // The table is about to change, so the files a dump import recorded for it no longer describe it.
+    if let Some(ref whiskey) = self.whiskey {
+      forget_table_hashes(whiskey, table_name);
+    }
+
+    let (table_adapter, load_table_name) = try!(self.db_adapter.begin_table(
+      table_name.to_owned(),
+      column_types.clone(),
+      !result.is_incremental,
+    ));
+    // A snapshot is loaded into a table of its own, so it doesn't need merging.
+    let should_bulk_load = !result.is_incremental && self.db_adapter.supports_bulk_load();
+
+    let mut import_res = Ok(());
+    for path in result.files.iter() {
+      import_res = self.import_file(
+        &table_adapter,
+        &load_table_name,
+        path,
+        &key_column,
+        &column_types,
+        result.is_incremental,
+        should_bulk_load,
+      );
+      if import_res.is_err() {
+        break;
+      }
+    }
+
+    match import_res {
+      Ok(()) => try!(table_adapter.finish_table(table_name.to_owned(), load_table_name)),
+      Err(err) => {
+        if let Err(abandon_err) = table_adapter.abandon_table(table_name.to_owned(), load_table_name) {
+          warn!("Failed to throw away the changes to table: {}", table_name);
+          warn!("{:?}", abandon_err);
+        }
+        return Err(err);
+      }
+    }
+
+    Ok(result)
+  }
+
+  /// Imports a single gzipped JSONL file of DAP records.
+  ///
+  /// Every record has a `key`, a `value` unless it's a delete, and a `meta` with its `action`.
+  /// Incremental records replace the record with the same key, or delete it. Records are sent to
+  /// the database a batch at a time.
+  ///
+  /// * `db_adapter` - The Database Adapter the table is being imported with.
+  /// * `table_name` - The table to import into.
+  /// * `path` - The file to import.
+  /// * `key_column` - The column that identifies a record.
+  /// * `column_types` - The database types of the table's columns.
+  /// * `is_incremental` - Whether the records are changes to existing records.
+  /// * `should_bulk_load` - Whether to bulk load the records, instead of inserting them.
+  fn import_file(
+    &self,
+    db_adapter: &T,
+    table_name: &str,
+    path: &Path,
+    key_column: &str,
+    column_types: &BTreeMap<String, String>,
+    is_incremental: bool,
+    should_bulk_load: bool,
+  ) -> Result<()> {
+    trace!("Importing DAP file: {:?}", path);
+    let reader = BufReader::new(GzDecoder::new(try!(File::open(path))));
+    let batch_size = db_adapter.get_batch_size();
+    let mut batch = Vec::with_capacity(batch_size);
+
+    for line in reader.lines() {
+      let line = try!(line);
+      if line.trim().is_empty() {
+        continue;
+      }
+      let record: Value = try!(serde_json::from_str(&line));
+
+      let mut columns = BTreeMap::new();
+      for column_name in column_types.keys() {
+        let value = if column_name == key_column {
+          &record["key"][column_name]
+        } else {
+          &record["value"][column_name]
+        };
+        columns.insert(column_name.clone(), get_column_value(value));
+      }
+      let is_delete = is_incremental && record["meta"]["action"].as_str() == Some("D");
+      batch.push((columns, is_delete));
+
+      if batch.len() >= batch_size {
+        try!(self.import_batch(
+          db_adapter,
+          table_name,
+          key_column,
+          column_types,
+          batch,
+          is_incremental,
+          should_bulk_load,
+        ));
+        batch = Vec::with_capacity(batch_size);
+      }
+    }
+
+    // Import whatever is left over at the end of the file.
+    if !batch.is_empty() {
+      try!(self.import_batch(
+        db_adapter,
+        table_name,
+        key_column,
+        column_types,
+        batch,
+        is_incremental,
+        should_bulk_load,
+      ));
+    }
+    Ok(())
+  }
+
+  /// Imports a batch of DAP records from a single file into its table.
+  ///
+  /// Snapshot records are just inserted. Incremental records drop the existing records with the
+  /// same keys first, and only the last change for each key in the batch is applied, the same as
+  /// if each change had been applied after the one before it.
+  ///
+  /// * `db_adapter` - The Database Adapter the table is being imported with.
+  /// * `table_name` - The table the records are for.
+  /// * `key_column` - The column that identifies a record.
+  /// * `column_types` - The database types of the table's columns.
+  /// * `records` - The records to import, and whether each one is a delete.
+  /// * `is_incremental` - Whether the records are changes to existing records.
+  /// * `should_bulk_load` - Whether to bulk load the records, instead of inserting them.
+  fn import_batch(
+    &self,
+    db_adapter: &T,
+    table_name: &str,
+    key_column: &str,
+    column_types: &BTreeMap<String, String>,
+    records: Vec<(BTreeMap<String, Option<String>>, bool)>,
+    is_incremental: bool,
+    should_bulk_load: bool,
+  ) -> Result<()> {
+    if !is_incremental {
+      let rows = records.into_iter().map(|(columns, _)| columns).collect();
+      if should_bulk_load {
+        return self.bulk_load_batch(db_adapter, table_name, column_types, rows);
+      }
+      return db_adapter.insert_records(table_name.to_owned(), column_types.clone(), rows);
+    }
+
+    // Only keep the last change for each key.
+    let mut last_changes = BTreeMap::new();
+    for (columns, is_delete) in records.into_iter() {
+      match columns.get(key_column).cloned().and_then(|value| value) {
+        Some(key_value) => {
+          last_changes.insert(key_value, (columns, is_delete));
+        }
+        None => return Err(ErrorKind::DapUnsupportedKey(table_name.to_owned(), vec![key_column.to_owned()]).into()),
+      }
+    }
+    let key_values: Vec<String> = last_changes.keys().cloned().collect();
+    let rows: Vec<_> = last_changes
+      .into_iter()
+      .filter(|&(_, (_, is_delete))| !is_delete)
+      .map(|(_, (columns, _))| columns)
+      .collect();
+
+    try!(db_adapter.drop_records(
+      table_name.to_owned(),
+      column_types.clone(),
+      key_column.to_owned(),
+      key_values,
+    ));
+    db_adapter.insert_records(table_name.to_owned(), column_types.clone(), rows)
+  }
+
+  /// Bulk loads a batch of snapshot records, as if they came from a Canvas Data TSV file.
+  ///
+  /// Records with a value that can't be written to a TSV file are inserted instead.
+  ///
+  /// * `db_adapter` - The Database Adapter the table is being imported with.
+  /// * `table_name` - The table the records are for.
+  /// * `column_types` - The database types of the table's columns.
+  /// * `rows` - The records to load.
+  fn bulk_load_batch(
+    &self,
+    db_adapter: &T,
+    table_name: &str,
+    column_types: &BTreeMap<String, String>,
+    rows: Vec<BTreeMap<String, Option<String>>>,
+  ) -> Result<()> {
+    let column_names: Vec<String> = column_types.keys().cloned().collect();
+    let mut tsv = String::new();
+    let mut unloadable_rows = Vec::new();
+    for row in rows.into_iter() {
+      match get_tsv_line(&column_names, &row) {
+        Some(line) => tsv += &line,
+        None => unloadable_rows.push(row),
+      }
+    }
+
+    if !tsv.is_empty() {
+      try!(db_adapter.bulk_load(
+        table_name.to_owned(),
+        column_types.clone(),
+        column_names,
+        Box::new(Cursor::new(tsv.into_bytes())),
+      ));
+    }
+    if !unloadable_rows.is_empty() {
+      try!(db_adapter.insert_records(table_name.to_owned(), column_types.clone(), unloadable_rows));
+    }
+    Ok(())
+  }
+}
+
+/// Writes a record as a line of a Canvas Data TSV file, with `\N` for NULLs.
+///
+/// Returns `None` if a value has a tab, or a line break, or is itself `\N`, since those can't be
+/// told apart from the rest of the file.
+///
+/// * `column_names` - The names of the columns, in the order they're written.
+/// * `row` - The record to write.
+fn get_tsv_line(column_names: &[String], row: &BTreeMap<String, Option<String>>) -> Option<String> {
+  let mut values = Vec::with_capacity(column_names.len());
+  for column_name in column_names.iter() {
+    match row.get(column_name).cloned().unwrap_or(None) {
+      Some(value) => {
+        if value == "\\N" || value.contains(|character| character == '\t' || character == '\n' || character == '\r') {
+          return None;
+        }
+        values.push(value);
+      }
+      None => values.push("\\N".to_owned()),
+    }
+  }
+  Some(values.join("\t") + "\n")
+}
+
+/// Turns a JSON value from a DAP record into a column value.
+///
+/// Nulls, and missing values are NULL, objects, and arrays are kept as JSON text.
+///
+/// * `value` - The JSON value.
+fn get_column_value(value: &Value) -> Option<String> {
+  match *value {
+    Value::Null => None,
+    Value::String(ref string) => Some(string.clone()),
+    Value::Bool(boolean) => Some(boolean.to_string()),
+    Value::Number(ref number) => Some(number.to_string()),
+    Value::Array(_) | Value::Object(_) => Some(value.to_string()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Builds a record from pairs of column names, and values.
+  ///
+  /// * `columns` - The columns of the record.
+  fn row(columns: &[(&str, Option<&str>)]) -> BTreeMap<String, Option<String>> {
+    columns
+      .iter()
+      .map(|&(name, value)| (name.to_owned(), value.map(|value| value.to_owned())))
+      .collect()
+  }
+
+  #[test]
+  fn get_tsv_line_writes_nulls_and_values_in_column_order() {
+    let column_names = vec!["id".to_owned(), "name".to_owned(), "workflow_state".to_owned()];
+    let record = row(&[("workflow_state", None), ("name", Some("C:\\dir")), ("id", Some("1"))]);
+    assert_eq!(get_tsv_line(&column_names, &record), Some("1\tC:\\dir\t\\N\n".to_owned()));
+  }
+
+  #[test]
+  fn get_tsv_line_refuses_values_it_cant_write() {
+    let column_names = vec!["id".to_owned(), "name".to_owned()];
+    assert_eq!(get_tsv_line(&column_names, &row(&[("id", Some("1")), ("name", Some("a\tb"))])), None);
+    assert_eq!(get_tsv_line(&column_names, &row(&[("id", Some("1")), ("name", Some("a\nb"))])), None);
+    assert_eq!(get_tsv_line(&column_names, &row(&[("id", Some("1")), ("name", Some("\\N"))])), None);
+  }
+
+  #[test]
+  fn get_column_value_keeps_nested_values_as_json() {
+    assert_eq!(get_column_value(&Value::Null), None);
+    assert_eq!(get_column_value(&json!("a")), Some("a".to_owned()));
+    assert_eq!(get_column_value(&json!(12)), Some("12".to_owned()));
+    assert_eq!(get_column_value(&json!({"a": [1]})), Some("{\"a\":[1]}".to_owned()));
+  }
+}
@@ -2,24 +2,99 @@
 //! This will control all the connections/inserts/updates/etc.
 
 use errors::*;
-use r2d2::{ManageConnection, Pool};
+use r2d2::{self, ManageConnection, Pool, PooledConnection};
 use std::clone::Clone;
+use std::cmp::min;
 use std::collections::BTreeMap;
+use std::io::{self, BufRead, Read};
+#[cfg(feature = "mysql_compat")]
+use std::io::Write;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
 use settings::{DatabaseType, Settings};
 use type_converter::get_cast_as;
 
 #[cfg(feature = "postgres_compat")]
 use r2d2_postgres::{TlsMode, PostgresConnectionManager};
 
+#[cfg(feature = "mysql_compat")]
+use mysql::LocalInfileHandler;
 #[cfg(feature = "mysql_compat")]
 use mysql_pool::{CreateManager, MysqlConnectionManager};
 
+/// The most bytes a single Postgres statement is built up to. Postgres itself allows far more,
+/// but there's no point in holding that much of a batch in memory at once.
+#[cfg(feature = "postgres_compat")]
+const POSTGRES_MAX_STATEMENT_BYTES: usize = 16 * 1024 * 1024;
+/// The `max_allowed_packet` to assume if the MySQL server can't be asked for it (the MySQL 5.7 default).
+#[cfg(feature = "mysql_compat")]
+const DEFAULT_MYSQL_MAX_ALLOWED_PACKET: u64 = 4 * 1024 * 1024;
+/// The character set Mysql tables are created with, and files are bulk loaded with.
+#[cfg(feature = "mysql_compat")]
+const MYSQL_CHARACTER_SET: &'static str = "utf8mb4";
+/// How many bytes of `max_allowed_packet` to leave for the packet header, and the like.
+#[cfg(feature = "mysql_compat")]
+const MYSQL_PACKET_SLACK_BYTES: usize = 1024;
+/// What's added to the name of a table to get the name of its staging table.
+const STAGING_TABLE_SUFFIX: &'static str = "_cdl_staging";
+/// What's added to the name of a table to get the name it has while it's being replaced.
+#[cfg(feature = "mysql_compat")]
+const REPLACED_TABLE_SUFFIX: &'static str = "_cdl_replaced";
+
+/// Gets how many connections the pool should have room for.
+///
+/// Every table imported at once holds a connection for its whole import, so there's room for
+/// one per table, plus a couple for the importer's own queries.
+///
+/// * `settings` - The settings with the most tables to import at once.
+fn get_pool_size(settings: &Settings) -> u32 {
+  settings.get_database_max_concurrent_tables() as u32 + 2
+}
+
 /// The Database Client Structure.
 pub struct DatabaseClient<T: ManageConnection> {
   /// The Type of the Database.
   pub db_type: DatabaseType,
   /// The Underlying Connection Pool.
   underlying_pool: Pool<T>,
+  /// The most rows to insert at once.
+  batch_size: usize,
+  /// The most bytes a single statement can be.
+  max_statement_bytes: usize,
+  /// The most tables to import at once.
+  max_concurrent_tables: usize,
+  /// Whether the database allows bulk loading files.
+  can_bulk_load: bool,
+  /// The connection every statement is run on, while a table is imported in a transaction.
+  pinned_connection: Option<Arc<Mutex<PooledConnection<T>>>>,
+}
+
+/// A connection to the database, either the pinned one, or one from the pool.
+enum DatabaseConnection<'a, T: ManageConnection> {
+  /// The connection pinned for a table's transaction.
+  Pinned(MutexGuard<'a, PooledConnection<T>>),
+  /// A connection from the pool.
+  Pooled(PooledConnection<T>),
+}
+
+impl<'a, T: ManageConnection> Deref for DatabaseConnection<'a, T> {
+  type Target = T::Connection;
+
+  fn deref(&self) -> &T::Connection {
+    match *self {
+      DatabaseConnection::Pinned(ref connection) => &***connection,
+      DatabaseConnection::Pooled(ref connection) => &**connection,
+    }
+  }
+}
+
+impl<'a, T: ManageConnection> DerefMut for DatabaseConnection<'a, T> {
+  fn deref_mut(&mut self) -> &mut T::Connection {
+    match *self {
+      DatabaseConnection::Pinned(ref mut connection) => &mut ***connection,
+      DatabaseConnection::Pooled(ref mut connection) => &mut **connection,
+    }
+  }
 }
 
 impl<T: ManageConnection> Clone for DatabaseClient<T> {
@@ -27,6 +102,11 @@ impl<T: ManageConnection> Clone for DatabaseClient<T> {
     DatabaseClient {
       db_type: self.db_type.clone(),
       underlying_pool: self.underlying_pool.clone(),
+      batch_size: self.batch_size,
+      max_statement_bytes: self.max_statement_bytes,
+      max_concurrent_tables: self.max_concurrent_tables,
+      can_bulk_load: self.can_bulk_load,
+      pinned_connection: self.pinned_connection.clone(),
     }
   }
 }
@@ -36,6 +116,22 @@ pub trait ImportDatabaseAdapter {
   /// Gets the Database Type.
   fn get_db_type(&self) -> DatabaseType;
 
+  /// Gets the most rows that should be handed to `insert_records` at once.
+  fn get_batch_size(&self) -> usize;
+
+  /// Gets the most tables that should be imported at once.
+  ///
+  /// Each table being imported holds a connection from the pool, which has room for this many.
+  fn get_max_concurrent_tables(&self) -> usize;
+
+  /// Gets whether the Database can bulk load a TSV file with `bulk_load`.
+  fn supports_bulk_load(&self) -> bool;
+
+  /// Checks if a Table exists in the Database.
+  ///
+  /// * `table_name` - The Table name to check for.
+  fn table_exists(&self, table_name: String) -> Result<bool>;
+
   /// Drops a Table in the Database.
   ///
   /// * `table_name` - The Table name to Drop.
@@ -47,6 +143,20 @@ pub trait ImportDatabaseAdapter {
   /// * `columns` - The column definition to create <column_name, column_type>.
   fn create_table(&self, table_name: String, columns: BTreeMap<String, String>) -> Result<()>;
 
+  /// Adds a Column to an existing Table in the Database.
+  ///
+  /// * `table_name` - The Table name to add the column to.
+  /// * `column_name` - The name of the column to add.
+  /// * `column_type` - The type of the column to add.
+  fn add_column(&self, table_name: String, column_name: String, column_type: String) -> Result<()>;
+
+  /// Changes the type of a Column in an existing Table in the Database.
+  ///
+  /// * `table_name` - The Table name the column is in.
+  /// * `column_name` - The name of the column to change.
+  /// * `column_type` - The new type of the column.
+  fn alter_column_type(&self, table_name: String, column_name: String, column_type: String) -> Result<()>;
+
   /// Drops a Record in the Database.
   ///
   /// * `table_name` - The Table Name to drop from.
@@ -72,10 +182,349 @@ pub trait ImportDatabaseAdapter {
     column_types: BTreeMap<String, String>,
     columns: BTreeMap<String, Option<String>>,
   ) -> Result<()>;
+
+  /// Drops every Record matching one of a list of values in the Database.
+  ///
+  /// * `table_name` - The Table Name to drop from.
+  /// * `column_types` - The types of columns
+  /// * `column_name` - The column name to use in the WHERE clause.
+  /// * `values` - The column values to use in the WHERE clause.
+  fn drop_records(
+    &self,
+    table_name: String,
+    column_types: BTreeMap<String, String>,
+    column_name: String,
+    values: Vec<String>,
+  ) -> Result<()>;
+
+  /// Inserts a batch of Records into the Database, using as few statements as possible.
+  ///
+  /// Every record needs to have the same columns.
+  ///
+  /// * `table_name` - The table name to insert the records into.
+  /// * `column_types` - The types of columns to use.
+  /// * `records` - The records to insert into the table <column_name, column_value>.
+  fn insert_records(
+    &self,
+    table_name: String,
+    column_types: BTreeMap<String, String>,
+    records: Vec<BTreeMap<String, Option<String>>>,
+  ) -> Result<()>;
+
+  /// Starts importing a Table, so none of the changes made to it are seen until `finish_table`.
+  ///
+  /// Creates the Table if it doesn't exist, after dropping it if it's being replaced. Returns the
+  /// Database Adapter to make the changes with, and the name of the Table to make them to, which
+  /// may be a staging table.
+  ///
+  /// * `table_name` - The Table name to import.
+  /// * `columns` - The column definition of the table <column_name, column_type>.
+  /// * `should_replace` - Whether the Table is being dropped, and loaded again from scratch.
+  fn begin_table(
+    &self,
+    table_name: String,
+    columns: BTreeMap<String, String>,
+    should_replace: bool,
+  ) -> Result<(Self, String)>
+  where
+    Self: Sized;
+
+  /// Makes the changes to a Table started with `begin_table` seen, all at once.
+  ///
+  /// * `table_name` - The Table name being imported.
+  /// * `load_table_name` - The Table name `begin_table` returned.
+  fn finish_table(&self, table_name: String, load_table_name: String) -> Result<()>;
+
+  /// Throws away the changes to a Table started with `begin_table`.
+  ///
+  /// * `table_name` - The Table name being imported.
+  /// * `load_table_name` - The Table name `begin_table` returned.
+  fn abandon_table(&self, table_name: String, load_table_name: String) -> Result<()>;
+
+  /// Bulk loads the rows of a Canvas Data TSV file into a Table, without checking for existing rows.
+  ///
+  /// * `table_name` - The table name to load the rows into.
+  /// * `column_types` - The types of columns to use.
+  /// * `column_names` - The names of the columns in the file, in order.
+  /// * `data` - The decompressed file.
+  fn bulk_load(
+    &self,
+    table_name: String,
+    column_types: BTreeMap<String, String>,
+    column_names: Vec<String>,
+    data: Box<BufRead + Send>,
+  ) -> Result<()>;
+}
+
+/// Reads the lines of a Canvas Data TSV file in the text format of the database's bulk loaders.
+///
+/// Every line has to have exactly the number of columns being loaded, since files are always
+/// loaded with the schema they're on. Backslashes other than the ones in `\N` are escaped, so
+/// values are loaded as they are in the file, like with an `INSERT`.
+pub struct TsvReader<R: BufRead> {
+  /// The decompressed file.
+  data: R,
+  /// The number of columns being loaded.
+  column_count: usize,
+  /// The current normalized line.
+  buffer: Vec<u8>,
+  /// How much of the current line has been read.
+  position: usize,
+}
+
+impl<R: BufRead> TsvReader<R> {
+  /// Creates a new TSV Reader.
+  ///
+  /// * `data` - The decompressed file.
+  /// * `column_count` - The number of columns being loaded.
+  pub fn new(data: R, column_count: usize) -> Self {
+    TsvReader {
+      data: data,
+      column_count: column_count,
+      buffer: Vec::new(),
+      position: 0,
+    }
+  }
+
+  /// Normalizes a single line, without its line ending.
+  ///
+  /// * `line` - The line to normalize.
+  fn normalize_line(&self, line: &str) -> io::Result<Vec<u8>> {
+    let values: Vec<String> = line
+      .split('\t')
+      .map(|value| if value == "\\N" {
+        value.to_owned()
+      } else {
+        value.replace("\\", "\\\\")
+      })
+      .collect();
+    if values.len() != self.column_count {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("A line has {} columns, expected {} columns", values.len(), self.column_count),
+      ));
+    }
+
+    let mut normalized = values.join("\t");
+    normalized.push('\n');
+    Ok(normalized.into_bytes())
+  }
+}
+
+impl<R: BufRead> Read for TsvReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    while self.position >= self.buffer.len() {
+      let mut line = String::new();
+      if try!(self.data.read_line(&mut line)) == 0 {
+        return Ok(0);
+      }
+      self.buffer = try!(self.normalize_line(line.trim_right_matches('\n').trim_right_matches('\r')));
+      self.position = 0;
+    }
+
+    let count = min(buf.len(), self.buffer.len() - self.position);
+    buf[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+    self.position += count;
+    Ok(count)
+  }
+}
+
+impl<T: ManageConnection> DatabaseClient<T> {
+  /// Gets a connection to run statements on, the pinned one if there is one.
+  fn get_connection(&self) -> ::std::result::Result<DatabaseConnection<T>, r2d2::Error> {
+    match self.pinned_connection {
+      Some(ref pinned) => Ok(DatabaseConnection::Pinned(
+        pinned.lock().unwrap_or_else(|poisoned| poisoned.into_inner()),
+      )),
+      None => self.underlying_pool.get().map(DatabaseConnection::Pooled),
+    }
+  }
+
+  /// Creates a copy of this client that runs every statement on the same connection from the pool.
+  fn pin_connection(&self) -> ::std::result::Result<Self, r2d2::Error> {
+    let connection = try!(self.underlying_pool.get());
+    Ok(DatabaseClient {
+      db_type: self.db_type.clone(),
+      underlying_pool: self.underlying_pool.clone(),
+      batch_size: self.batch_size,
+      max_statement_bytes: self.max_statement_bytes,
+      max_concurrent_tables: self.max_concurrent_tables,
+      can_bulk_load: self.can_bulk_load,
+      pinned_connection: Some(Arc::new(Mutex::new(connection))),
+    })
+  }
+
+  /// Escapes a column name, since `default`, and (for Mysql) `generated` are reserved words.
+  ///
+  /// * `column_name` - The column name to escape.
+  fn escape_column_name(&self, column_name: &str) -> String {
+    match self.db_type {
+      DatabaseType::Mysql => column_name.replace("default", "_default").replace("generated", "_generated"),
+      DatabaseType::Psql => column_name.replace("default", "_default"),
+    }
+  }
+
+  /// Quotes a value as a string literal, escaped so it's stored exactly as it is in the file.
+  ///
+  /// This is the same value a bulk load of the file stores, as `TsvReader` escapes backslashes the
+  /// same way for the bulk loaders.
+  ///
+  /// * `value` - The value to quote.
+  fn quote_value(&self, value: &str) -> String {
+    let escaped = value.replace("\\", "\\\\").replace("'", "''");
+    match self.db_type {
+      // An escape string reads backslashes the same no matter what standard_conforming_strings is.
+      DatabaseType::Psql => format!("E'{}'", escaped),
+      DatabaseType::Mysql => format!("'{}'", escaped),
+    }
+  }
+
+  /// Formats a value for use in a statement, casting it as the right type to prevent db errors.
+  ///
+  /// * `column_type` - The type of the column the value is for.
+  /// * `value` - The value to format, `None` being NULL.
+  fn format_value(&self, column_type: &str, value: &Option<String>) -> String {
+    let value = match *value {
+      Some(ref value) => self.quote_value(value),
+      None => return "NULL".to_owned(),
+    };
+    let cast_as = get_cast_as(column_type.to_owned(), self.db_type.clone());
+    if cast_as == "" {
+      value
+    } else {
+      match self.db_type {
+        DatabaseType::Mysql => format!("CAST({} AS {})", value, cast_as),
+        DatabaseType::Psql => format!("{}::{}", value, cast_as),
+      }
+    }
+  }
+
+  /// Joins a list of formatted values onto the end of a statement prefix, starting a new
+  /// statement whenever one would grow past the most bytes a statement can be.
+  ///
+  /// * `prefix` - What every statement starts with.
+  /// * `suffix` - What every statement ends with.
+  /// * `values` - The formatted values to join with commas.
+  fn build_statements(&self, prefix: &str, suffix: &str, values: Vec<String>) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut statement = prefix.to_owned();
+    let mut values_in_statement = 0;
+
+    for value in values.into_iter() {
+      // A single value too big for a statement still gets sent, so the database can complain.
+      if values_in_statement > 0 && statement.len() + value.len() + suffix.len() + 1 > self.max_statement_bytes {
+        statement += suffix;
+        statements.push(statement);
+        statement = prefix.to_owned();
+        values_in_statement = 0;
+      }
+      if values_in_statement > 0 {
+        statement += ",";
+      }
+      statement += &value;
+      values_in_statement += 1;
+    }
+    if values_in_statement > 0 {
+      statement += suffix;
+      statements.push(statement);
+    }
+
+    statements
+  }
+
+  /// Builds the multi-row `INSERT INTO () VALUES (), ()` statements for a batch of records.
+  ///
+  /// * `table_name` - The table name to insert the records into.
+  /// * `column_types` - The types of columns to use.
+  /// * `records` - The records to insert.
+  fn build_insert_statements(
+    &self,
+    table_name: &str,
+    column_types: &BTreeMap<String, String>,
+    records: Vec<BTreeMap<String, Option<String>>>,
+  ) -> Vec<String> {
+    let column_names: Vec<String> = match records.first() {
+      Some(record) => record.keys().cloned().collect(),
+      None => return Vec::new(),
+    };
+    let prefix = format!(
+      "INSERT INTO {} ({}) VALUES ",
+      table_name,
+      column_names
+        .iter()
+        .map(|column_name| self.escape_column_name(column_name))
+        .collect::<Vec<_>>()
+        .join(",")
+    );
+
+    let rows: Vec<String> = records
+      .iter()
+      .map(|record| {
+        let values: Vec<String> = column_names
+          .iter()
+          .map(|column_name| {
+            self.format_value(
+              column_types.get(column_name).unwrap(),
+              record.get(column_name).unwrap_or(&None),
+            )
+          })
+          .collect();
+        format!("({})", values.join(","))
+      })
+      .collect();
+
+    self.build_statements(&prefix, "", rows)
+  }
+
+  /// Builds the `DELETE FROM WHERE IN ()` statements for a list of values.
+  ///
+  /// * `table_name` - The Table Name to drop from.
+  /// * `column_types` - The types of columns
+  /// * `column_name` - The column name to use in the WHERE clause.
+  /// * `values` - The column values to use in the WHERE clause.
+  fn build_delete_statements(
+    &self,
+    table_name: &str,
+    column_types: &BTreeMap<String, String>,
+    column_name: &str,
+    values: Vec<String>,
+  ) -> Vec<String> {
+    let the_type = column_types.get(column_name).unwrap();
+    let values: Vec<String> = values
+      .into_iter()
+      .map(|value| self.format_value(the_type, &Some(value)))
+      .collect();
+
+    self.build_statements(
+      &format!("DELETE FROM {} WHERE {} IN (", table_name, self.escape_column_name(column_name)),
+      ")",
+      values,
+    )
+  }
 }
 
 #[cfg(feature = "postgres_compat")]
 impl DatabaseClient<PostgresConnectionManager> {
+  /// Runs a statement that controls a transaction, or swaps tables.
+  ///
+  /// * `statement` - The statement to run.
+  fn run_transaction_statement(&self, statement: &str) -> Result<()> {
+    trace!("Running: {}", statement);
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::PostgresErr.into());
+    }
+    let connection = connection.unwrap();
+
+    let result = connection.batch_execute(statement);
+    if result.is_err() {
+      error!("{} err", statement);
+      error!("{:?}", result.err().unwrap());
+      return Err(ErrorKind::PostgresErr.into());
+    }
+    Ok(())
+  }
+
   /// Creates a New Database Client for Postgres.
   ///
   /// `settings` - The underlying settings object to configure ourselves with.
@@ -85,18 +534,44 @@ impl DatabaseClient<PostgresConnectionManager> {
       return Err(ErrorKind::PostgresErr.into());
     }
     let manager = manager.unwrap();
-    let pool = Pool::new(manager).expect(
-      "Failed to turn connection into pool. This should never happen",
-    );
+    let pool = Pool::builder()
+      .max_size(get_pool_size(settings))
+      .build(manager)
+      .expect("Failed to turn connection into pool. This should never happen");
     Ok(DatabaseClient::<PostgresConnectionManager> {
       db_type: DatabaseType::Psql,
       underlying_pool: pool,
+      batch_size: try!(settings.get_database_batch_size()),
+      max_concurrent_tables: settings.get_database_max_concurrent_tables(),
+      max_statement_bytes: POSTGRES_MAX_STATEMENT_BYTES,
+      can_bulk_load: true,
+      pinned_connection: None,
     })
   }
 }
 
 #[cfg(feature = "mysql_compat")]
 impl DatabaseClient<MysqlConnectionManager> {
+  /// Runs a statement that controls a transaction, or renames tables.
+  ///
+  /// * `statement` - The statement to run.
+  fn run_transaction_statement(&self, statement: &str) -> Result<()> {
+    trace!("Running: {}", statement);
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::MysqlErr.into());
+    }
+    let mut connection = connection.unwrap();
+
+    let result = connection.query(statement);
+    if result.is_err() {
+      error!("{} err", statement);
+      error!("{:?}", result.err().unwrap());
+      return Err(ErrorKind::MysqlErr.into());
+    }
+    Ok(())
+  }
+
   /// Creates a New Database Client for Mysql.
   ///
   /// `settings` - The underlying settings object to configure ourselves with.
@@ -106,12 +581,41 @@ impl DatabaseClient<MysqlConnectionManager> {
       return Err(ErrorKind::MysqlErr.into());
     }
     let manager = manager.unwrap();
-    let pool = Pool::new(manager).expect(
-      "Failed to turn a connection into pool. This should never happen",
-    );
+    let pool = Pool::builder()
+      .max_size(get_pool_size(settings))
+      .build(manager)
+      .expect("Failed to turn a connection into pool. This should never happen");
+
+    // Mysql refuses any statement bigger than `max_allowed_packet`, so find out what it is.
+    let max_allowed_packet = pool
+      .get()
+      .ok()
+      .and_then(|mut connection| connection.first::<_, u64>("SELECT @@max_allowed_packet").ok())
+      .and_then(|max_allowed_packet| max_allowed_packet)
+      .unwrap_or(DEFAULT_MYSQL_MAX_ALLOWED_PACKET);
+    debug!("Mysql max_allowed_packet is: {}", max_allowed_packet);
+
+    // LOAD DATA LOCAL INFILE is refused unless the server turns local_infile on, which Mysql 8
+    // doesn't by default, so fall back to inserts if it's off, or can't be checked.
+    let local_infile = pool
+      .get()
+      .ok()
+      .and_then(|mut connection| connection.first::<_, u64>("SELECT @@local_infile").ok())
+      .and_then(|local_infile| local_infile)
+      .map(|local_infile| local_infile != 0)
+      .unwrap_or(false);
+    if !local_infile {
+      info!("Mysql local_infile is off, tables will be loaded with INSERTs instead.");
+    }
+
     Ok(DatabaseClient::<MysqlConnectionManager> {
       db_type: DatabaseType::Mysql,
       underlying_pool: pool,
+      batch_size: try!(settings.get_database_batch_size()),
+      max_concurrent_tables: settings.get_database_max_concurrent_tables(),
+      max_statement_bytes: (max_allowed_packet as usize).saturating_sub(MYSQL_PACKET_SLACK_BYTES),
+      can_bulk_load: local_infile,
+      pinned_connection: None,
     })
   }
 }
@@ -123,10 +627,43 @@ impl ImportDatabaseAdapter for DatabaseClient<PostgresConnectionManager> {
     self.db_type.clone()
   }
 
+  fn get_batch_size(&self) -> usize {
+    self.batch_size
+  }
+
+  fn get_max_concurrent_tables(&self) -> usize {
+    self.max_concurrent_tables
+  }
+
+  fn supports_bulk_load(&self) -> bool {
+    self.can_bulk_load
+  }
+
+  fn table_exists(&self, table_name: String) -> Result<bool> {
+    trace!("table_exists was called for: [ {} ]", table_name);
+    // Get a connection from the pool.
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::PostgresErr.into());
+    }
+    let connection = connection.unwrap();
+
+    let result = connection.query(
+      "SELECT 1 FROM information_schema.tables WHERE table_schema = current_schema() AND table_name = $1",
+      &[&table_name],
+    );
+    if result.is_err() {
+      error!("table_exists err");
+      error!("{:?}", result.err().unwrap());
+      return Err(ErrorKind::PostgresErr.into());
+    }
+    Ok(!result.unwrap().is_empty())
+  }
+
   fn drop_table(&self, table_name: String) -> Result<()> {
     trace!("drop_table was called for: [ {} ]", table_name);
     // Get a aconnection from the pool.
-    let connection = self.underlying_pool.get();
+    let connection = self.get_connection();
     if connection.is_err() {
       return Err(ErrorKind::PostgresErr.into());
     }
@@ -147,7 +684,7 @@ impl ImportDatabaseAdapter for DatabaseClient<PostgresConnectionManager> {
   fn create_table(&self, table_name: String, columns: BTreeMap<String, String>) -> Result<()> {
     trace!("create_table was called for: [ {} ]", table_name);
     // Get a Connection from the underlying DB Connection Pool.
-    let connection = self.underlying_pool.get();
+    let connection = self.get_connection();
     if connection.is_err() {
       return Err(ErrorKind::PostgresErr.into());
     }
@@ -181,6 +718,77 @@ impl ImportDatabaseAdapter for DatabaseClient<PostgresConnectionManager> {
     }
   }
 
+  fn add_column(&self, table_name: String, column_name: String, column_type: String) -> Result<()> {
+    trace!(
+      "add_column was called for table: {} column: {} type: {}",
+      table_name,
+      column_name,
+      column_type
+    );
+    // Get a Connection from the underlying pool.
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::PostgresErr.into());
+    }
+    let connection = connection.unwrap();
+
+    // Execute the alter table statement. `default` is a reserved word.
+    let result = connection.execute(
+      &format!(
+        "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}",
+        table_name,
+        column_name.replace("default", "_default"),
+        column_type
+      ),
+      &[],
+    );
+    if result.is_err() {
+      error!("add_column err");
+      error!("{:?}", result.err().unwrap());
+      return Err(ErrorKind::PostgresErr.into());
+    } else {
+      trace!("add_column was successful");
+      return Ok(());
+    }
+  }
+
+  fn alter_column_type(&self, table_name: String, column_name: String, column_type: String) -> Result<()> {
+    trace!(
+      "alter_column_type was called for table: {} column: {} type: {}",
+      table_name,
+      column_name,
+      column_type
+    );
+    // Get a Connection from the underlying pool.
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::PostgresErr.into());
+    }
+    let connection = connection.unwrap();
+
+    // Execute the alter table statement, casting the existing values to the new type.
+    let escaped_column_name = column_name.replace("default", "_default");
+    let result = connection.execute(
+      &format!(
+        "ALTER TABLE {} ALTER COLUMN {} TYPE {} USING {}::{}",
+        table_name,
+        escaped_column_name,
+        column_type,
+        escaped_column_name,
+        column_type
+      ),
+      &[],
+    );
+    if result.is_err() {
+      error!("alter_column_type err");
+      error!("{:?}", result.err().unwrap());
+      return Err(ErrorKind::PostgresErr.into());
+    } else {
+      trace!("alter_column_type was successful");
+      return Ok(());
+    }
+  }
+
   fn drop_record(
     &self,
     table_name: String,
@@ -195,7 +803,7 @@ impl ImportDatabaseAdapter for DatabaseClient<PostgresConnectionManager> {
       value
     );
     // Get a Connection from the underlying pool.
-    let connection = self.underlying_pool.get();
+    let connection = self.get_connection();
     if connection.is_err() {
       return Err(ErrorKind::PostgresErr.into());
     }
@@ -211,16 +819,7 @@ impl ImportDatabaseAdapter for DatabaseClient<PostgresConnectionManager> {
     let the_type = column_types.get(&column_name).unwrap();
 
     // Make sure the column gets inserted as the right type to prevent db errors.
-    let cast_as = get_cast_as(the_type.to_owned(), self.db_type.clone());
-    if cast_as == "" {
-      prepared += &format!("{:?}", value.replace("'", "").replace("\"", "")).replace("\"", "'");
-    } else {
-      prepared += &format!(
-        "{:?}::{}",
-        value.replace("'", "").replace("\"", ""),
-        cast_as
-      ).replace("\"", "'");
-    }
+    prepared += &self.format_value(the_type, &Some(value));
 
     // Execute the preapred delete statement.
     let statement = connection.execute(&prepared, &[]);
@@ -240,66 +839,165 @@ impl ImportDatabaseAdapter for DatabaseClient<PostgresConnectionManager> {
     columns: BTreeMap<String, Option<String>>,
   ) -> Result<()> {
     trace!("insert_record was called for table: {}", table_name);
-    // Get a connection from the underlying pool.
-    let connection = self.underlying_pool.get();
+    self.insert_records(table_name, column_types, vec![columns])
+  }
+
+  fn drop_records(
+    &self,
+    table_name: String,
+    column_types: BTreeMap<String, String>,
+    column_name: String,
+    values: Vec<String>,
+  ) -> Result<()> {
+    trace!(
+      "drop_records was called for table: {} on column: {} with {} values",
+      table_name,
+      column_name,
+      values.len()
+    );
+    // Get a Connection from the underlying pool, and use it for every statement.
+    let connection = self.get_connection();
     if connection.is_err() {
       return Err(ErrorKind::PostgresErr.into());
     }
     let connection = connection.unwrap();
 
-    // Create the insert into statement.
-    let mut insert_string = format!("INSERT INTO {} (", table_name);
-    let mut types = BTreeMap::new();
-
-    // We need to know all the types of the keys for the INSERT INTO () VALUES ()
-    for (pos, key) in columns.keys().enumerate() {
-      insert_string += &format!("{},", key.replace("default", "_default"));
-      types.insert(pos, column_types.get(key).unwrap().to_owned());
-    }
-    let mut len = insert_string.len();
-    // Remove Trailing Comma.
-    insert_string.truncate(len - 1);
-
-    // Loop over actual values.
-    insert_string += ") VALUES (";
-    for (pos, val) in columns.values().enumerate() {
-      // Handle Nulls
-      if val.is_none() {
-        insert_string += "NULL,";
-      } else {
-        let the_type = types.get(&pos).unwrap();
-        // Cast the value as the right type.
-        let cast_as = get_cast_as(the_type.to_owned(), self.db_type.clone());
-        if cast_as == "" {
-          insert_string += &format!(
-            "{:?},",
-            val.clone().unwrap().replace("'", "").replace("\"", "")
-          ).replace("\"", "'");
-        } else {
-          insert_string += &format!(
-            "{:?}::{},",
-            val.clone().unwrap().replace("'", "").replace("\"", ""),
-            cast_as
-          ).replace("\"", "'");
-        }
+    for statement in self.build_delete_statements(&table_name, &column_types, &column_name, values) {
+      let result = connection.execute(&statement, &[]);
+      if result.is_err() {
+        error!("drop_records err");
+        error!("{:?}", result.err().unwrap());
+        return Err(ErrorKind::PostgresErr.into());
       }
     }
-    len = insert_string.len();
+    Ok(())
+  }
 
-    // Remove Trailing Comma.
-    insert_string.truncate(len - 1);
-    insert_string += ")";
-    debug!("Insert_record string looks like: \n {}", insert_string);
+  fn insert_records(
+    &self,
+    table_name: String,
+    column_types: BTreeMap<String, String>,
+    records: Vec<BTreeMap<String, Option<String>>>,
+  ) -> Result<()> {
+    trace!("insert_records was called for table: {} with {} records", table_name, records.len());
+    // Get a Connection from the underlying pool, and use it for every statement.
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::PostgresErr.into());
+    }
+    let connection = connection.unwrap();
 
-    // Execute.
-    let statement = connection.execute(&insert_string, &[]);
+    for statement in self.build_insert_statements(&table_name, &column_types, records) {
+      debug!("Insert_records string is: {} bytes", statement.len());
+      let result = connection.execute(&statement, &[]);
+      if result.is_err() {
+        error!("insert error");
+        error!("{:?}", result.err().unwrap());
+        return Err(ErrorKind::PostgresErr.into());
+      }
+    }
+    Ok(())
+  }
+
+  fn begin_table(
+    &self,
+    table_name: String,
+    columns: BTreeMap<String, String>,
+    should_replace: bool,
+  ) -> Result<(Self, String)> {
+    trace!("begin_table was called for: [ {} ]", table_name);
+    // Dropping the table would lock readers out of it for the whole load, so a replaced table
+    // is loaded into a staging table, and swapped in once it's done.
+    if should_replace {
+      let staging_table_name = format!("{}{}", table_name, STAGING_TABLE_SUFFIX);
+      try!(self.drop_table(staging_table_name.clone()));
+      try!(self.create_table(staging_table_name.clone(), columns));
+      return Ok((self.clone(), staging_table_name));
+    }
+
+    // Merging only locks the rows it changes, so the create, and merge go in one transaction.
+    let table_client = self.pin_connection();
+    if table_client.is_err() {
+      return Err(ErrorKind::PostgresErr.into());
+    }
+    let table_client = table_client.unwrap();
+    try!(table_client.run_transaction_statement("BEGIN"));
+    if let Err(err) = table_client.create_table(table_name.clone(), columns) {
+      let _ = table_client.run_transaction_statement("ROLLBACK");
+      return Err(err);
+    }
+
+    Ok((table_client, table_name))
+  }
+
+  fn finish_table(&self, table_name: String, load_table_name: String) -> Result<()> {
+    trace!("finish_table was called for: [ {} ]", table_name);
+    if load_table_name == table_name {
+      return self.run_transaction_statement("COMMIT");
+    }
+
+    // Swap the staging table in. The statements of a single query run in one transaction, so
+    // readers see either the old table or the new one, and are only held up for the swap.
+    self.run_transaction_statement(&format!(
+      "DROP TABLE IF EXISTS {}; ALTER TABLE {} RENAME TO {}",
+      table_name,
+      load_table_name,
+      table_name
+    ))
+  }
+
+  fn abandon_table(&self, table_name: String, load_table_name: String) -> Result<()> {
+    trace!("abandon_table was called for: [ {} ]", table_name);
+    if load_table_name == table_name {
+      self.run_transaction_statement("ROLLBACK")
+    } else {
+      self.drop_table(load_table_name)
+    }
+  }
+
+  fn bulk_load(
+    &self,
+    table_name: String,
+    _column_types: BTreeMap<String, String>,
+    column_names: Vec<String>,
+    data: Box<BufRead + Send>,
+  ) -> Result<()> {
+    trace!("bulk_load was called for table: {}", table_name);
+    // Get a connection from the underlying pool.
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::PostgresErr.into());
+    }
+    let connection = connection.unwrap();
+
+    // The files are almost in COPY's text format already, so stream them straight in.
+    let copy_string = format!(
+      "COPY {} ({}) FROM STDIN",
+      table_name,
+      column_names
+        .iter()
+        .map(|column_name| self.escape_column_name(column_name))
+        .collect::<Vec<_>>()
+        .join(",")
+    );
+    debug!("Bulk loading with: {}", copy_string);
+    let statement = connection.prepare(&copy_string);
     if statement.is_err() {
-      error!("insert error");
+      error!("bulk_load prepare err");
       error!("{:?}", statement.err().unwrap());
       return Err(ErrorKind::PostgresErr.into());
-    } else {
-      return Ok(());
     }
+    let statement = statement.unwrap();
+
+    let mut reader = TsvReader::new(data, column_names.len());
+    let result = statement.copy_in(&[], &mut reader);
+    if result.is_err() {
+      error!("bulk_load err");
+      error!("{:?}", result.err().unwrap());
+      return Err(ErrorKind::PostgresErr.into());
+    }
+    debug!("Bulk loaded {} rows into: {}", result.unwrap(), table_name);
+    Ok(())
   }
 }
 
@@ -311,11 +1009,44 @@ impl ImportDatabaseAdapter for DatabaseClient<MysqlConnectionManager> {
     self.db_type.clone()
   }
 
+  fn get_batch_size(&self) -> usize {
+    self.batch_size
+  }
+
+  fn get_max_concurrent_tables(&self) -> usize {
+    self.max_concurrent_tables
+  }
+
+  fn supports_bulk_load(&self) -> bool {
+    self.can_bulk_load
+  }
+
+  fn table_exists(&self, table_name: String) -> Result<bool> {
+    trace!("table_exists was called for: [ {} ]", table_name);
+    // Get connection from the underlying pool.
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::MysqlErr.into());
+    }
+    let mut connection = connection.unwrap();
+
+    let result = connection.prep_exec(
+      "SELECT 1 FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name = ?",
+      (table_name,),
+    );
+    if result.is_err() {
+      error!("table_exists err");
+      error!("{:?}", result.err().unwrap());
+      return Err(ErrorKind::MysqlErr.into());
+    }
+    Ok(result.unwrap().count() > 0)
+  }
+
   fn drop_table(&self, table_name: String) -> Result<()> {
     trace!("drop_table was called for: [ {} ]", table_name);
 
     // Get connection from the underlying pool.
-    let connection = self.underlying_pool.get();
+    let connection = self.get_connection();
     if connection.is_err() {
       return Err(ErrorKind::MysqlErr.into());
     }
@@ -336,7 +1067,7 @@ impl ImportDatabaseAdapter for DatabaseClient<MysqlConnectionManager> {
   fn create_table(&self, table_name: String, columns: BTreeMap<String, String>) -> Result<()> {
     trace!("create_table was called for: [ {} ]", table_name);
     // Get connection from the underlying pool.
-    let connection = self.underlying_pool.get();
+    let connection = self.get_connection();
     if connection.is_err() {
       return Err(ErrorKind::MysqlErr.into());
     }
@@ -358,7 +1089,7 @@ impl ImportDatabaseAdapter for DatabaseClient<MysqlConnectionManager> {
     // Remove Trailing newline, and comma.
     creation_string.truncate(len - 2);
     // Ensure Character set is utf8mb4.
-    creation_string += ") CHARACTER SET utf8mb4";
+    creation_string += &format!(") CHARACTER SET {}", MYSQL_CHARACTER_SET);
     trace!(
       "Using the following creation string: \n {}",
       creation_string
@@ -376,6 +1107,95 @@ impl ImportDatabaseAdapter for DatabaseClient<MysqlConnectionManager> {
     }
   }
 
+  fn add_column(&self, table_name: String, column_name: String, column_type: String) -> Result<()> {
+    trace!(
+      "add_column was called for table: {} column: {} type: {}",
+      table_name,
+      column_name,
+      column_type
+    );
+    // Get connection from the underlying pool.
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::MysqlErr.into());
+    }
+    let mut connection = connection.unwrap();
+    // `default`, and `generated` are reserved words.
+    let escaped_column_name = self.escape_column_name(&column_name);
+
+    // Mysql has no `ADD COLUMN IF NOT EXISTS`, so check for the column first, in case an earlier
+    // migration already added it.
+    let existing = connection
+      .prep_exec(
+        "SELECT 1 FROM information_schema.columns WHERE table_schema = DATABASE() AND table_name = ? \
+         AND column_name = ?",
+        (table_name.clone(), escaped_column_name.clone()),
+      )
+      .map(|result| result.count());
+    match existing {
+      Ok(0) => {}
+      Ok(_) => {
+        debug!("Column: {} already exists in table: {}", escaped_column_name, table_name);
+        return Ok(());
+      }
+      Err(err) => {
+        error!("add_column err");
+        error!("{:?}", err);
+        return Err(ErrorKind::MysqlErr.into());
+      }
+    }
+
+    // Execute the alter table statement.
+    let result = connection.query(&format!(
+      "ALTER TABLE {} ADD COLUMN {} {}",
+      table_name,
+      escaped_column_name,
+      column_type
+    ));
+    if result.is_err() {
+      error!("add_column err");
+      error!("{:?}", result.err().unwrap());
+      return Err(ErrorKind::MysqlErr.into());
+    } else {
+      trace!("add_column was successful");
+      return Ok(());
+    }
+  }
+
+  fn alter_column_type(&self, table_name: String, column_name: String, column_type: String) -> Result<()> {
+    trace!(
+      "alter_column_type was called for table: {} column: {} type: {}",
+      table_name,
+      column_name,
+      column_type
+    );
+    // Get connection from the underlying pool.
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::MysqlErr.into());
+    }
+    let mut connection = connection.unwrap();
+
+    // Execute the alter table statement. `default`, and `generated` are reserved words.
+    let result = connection.query(&format!(
+      "ALTER TABLE {} MODIFY COLUMN {} {}",
+      table_name,
+      column_name.replace("default", "_default").replace(
+        "generated",
+        "_generated",
+      ),
+      column_type
+    ));
+    if result.is_err() {
+      error!("alter_column_type err");
+      error!("{:?}", result.err().unwrap());
+      return Err(ErrorKind::MysqlErr.into());
+    } else {
+      trace!("alter_column_type was successful");
+      return Ok(());
+    }
+  }
+
   fn drop_record(
     &self,
     table_name: String,
@@ -390,7 +1210,7 @@ impl ImportDatabaseAdapter for DatabaseClient<MysqlConnectionManager> {
       value
     );
     // Grab a Connection from the pool.
-    let connection = self.underlying_pool.get();
+    let connection = self.get_connection();
     if connection.is_err() {
       return Err(ErrorKind::MysqlErr.into());
     }
@@ -406,16 +1226,7 @@ impl ImportDatabaseAdapter for DatabaseClient<MysqlConnectionManager> {
     let the_type = column_types.get(&column_name).unwrap();
 
     // Cast the type correctly.
-    let cast_as = get_cast_as(the_type.to_owned(), self.db_type.clone());
-    if cast_as == "" {
-      prepared += &format!("{:?}", value.replace("'", "").replace("\"", "")).replace("\"", "'");
-    } else {
-      prepared += &format!(
-        "CAST({:?} as {})",
-        value.replace("'", "").replace("\"", ""),
-        cast_as
-      ).replace("\"", "'");
-    }
+    prepared += &self.format_value(the_type, &Some(value));
 
     // Execute.
     let statement = connection.query(&prepared);
@@ -435,70 +1246,332 @@ impl ImportDatabaseAdapter for DatabaseClient<MysqlConnectionManager> {
     columns: BTreeMap<String, Option<String>>,
   ) -> Result<()> {
     trace!("insert_record was called for table: {}", table_name);
-    // Get connection from the underlying pool.
-    let connection = self.underlying_pool.get();
+    self.insert_records(table_name, column_types, vec![columns])
+  }
+
+  fn drop_records(
+    &self,
+    table_name: String,
+    column_types: BTreeMap<String, String>,
+    column_name: String,
+    values: Vec<String>,
+  ) -> Result<()> {
+    trace!(
+      "drop_records was called for table: {} on column: {} with {} values",
+      table_name,
+      column_name,
+      values.len()
+    );
+    // Grab a Connection from the pool, and use it for every statement.
+    let connection = self.get_connection();
     if connection.is_err() {
-      return Err(ErrorKind::PostgresErr.into());
+      return Err(ErrorKind::MysqlErr.into());
     }
     let mut connection = connection.unwrap();
 
-    // Start Preparing insert into statements.
-    let mut insert_string = format!("INSERT INTO {} (", table_name);
-    let mut types = BTreeMap::new();
+    for statement in self.build_delete_statements(&table_name, &column_types, &column_name, values) {
+      let result = connection.query(&statement);
+      if result.is_err() {
+        error!("drop_records err");
+        error!("{:?}", result.err().unwrap());
+        return Err(ErrorKind::MysqlErr.into());
+      }
+    }
+    Ok(())
+  }
 
-    // We need the types for INSERT INTO () VALUES (). Get Those.
-    for (pos, key) in columns.keys().enumerate() {
-      insert_string += &format!(
-        "{},",
-        key.replace("default", "_default").replace(
-          "generated",
-          "_generated",
-        )
-      );
-      types.insert(pos, column_types.get(key).unwrap().to_owned());
-    }
-    let mut len = insert_string.len();
-    // Remove trailing comma.
-    insert_string.truncate(len - 1);
-
-    // Start Inserting Values.
-    insert_string += ") VALUES (";
-    for (pos, val) in columns.values().enumerate() {
-      if val.is_none() {
-        // Handle NULLs.
-        insert_string += "NULL,";
-      } else {
-        let the_type = types.get(&pos).unwrap();
-        // Cast the type correctly.
-        let cast_as = get_cast_as(the_type.to_owned(), self.db_type.clone());
-        if cast_as == "" {
-          insert_string += &format!(
-            "{:?},",
-            val.clone().unwrap().replace("'", "").replace("\"", "")
-          ).replace("\"", "'");
-        } else {
-          insert_string += &format!(
-            "CAST({:?} AS {}),",
-            val.clone().unwrap().replace("'", "").replace("\"", ""),
-            cast_as
-          ).replace("\"", "'");
-        }
+  fn insert_records(
+    &self,
+    table_name: String,
+    column_types: BTreeMap<String, String>,
+    records: Vec<BTreeMap<String, Option<String>>>,
+  ) -> Result<()> {
+    trace!("insert_records was called for table: {} with {} records", table_name, records.len());
+    // Grab a Connection from the pool, and use it for every statement.
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::MysqlErr.into());
+    }
+    let mut connection = connection.unwrap();
+
+    for statement in self.build_insert_statements(&table_name, &column_types, records) {
+      debug!("Insert_records string is: {} bytes", statement.len());
+      let result = connection.query(&statement);
+      if result.is_err() {
+        error!("insert error");
+        error!("{:?}", result.err().unwrap());
+        return Err(ErrorKind::MysqlErr.into());
       }
     }
-    len = insert_string.len();
-    // Remove trailing commas.
-    insert_string.truncate(len - 1);
-    insert_string += ")";
-    debug!("Insert_record string looks like: \n {}", insert_string);
+    Ok(())
+  }
 
-    // Execute.
-    let statement = connection.query(&insert_string);
-    if statement.is_err() {
-      error!("insert error");
-      error!("{:?}", statement.err().unwrap());
+  fn begin_table(
+    &self,
+    table_name: String,
+    columns: BTreeMap<String, String>,
+    should_replace: bool,
+  ) -> Result<(Self, String)> {
+    trace!("begin_table was called for: [ {} ]", table_name);
+    // DDL can't be rolled back in Mysql, so a replaced table is loaded into a staging table, and
+    // swapped in once it's done.
+    if should_replace {
+      let staging_table_name = format!("{}{}", table_name, STAGING_TABLE_SUFFIX);
+      try!(self.drop_table(staging_table_name.clone()));
+      try!(self.create_table(staging_table_name.clone(), columns));
+      return Ok((self.clone(), staging_table_name));
+    }
+
+    // Merging only changes rows, so that can happen in a transaction once the table exists.
+    try!(self.create_table(table_name.clone(), columns));
+    let table_client = self.pin_connection();
+    if table_client.is_err() {
       return Err(ErrorKind::MysqlErr.into());
+    }
+    let table_client = table_client.unwrap();
+    try!(table_client.run_transaction_statement("START TRANSACTION"));
+
+    Ok((table_client, table_name))
+  }
+
+  fn finish_table(&self, table_name: String, load_table_name: String) -> Result<()> {
+    trace!("finish_table was called for: [ {} ]", table_name);
+    if load_table_name == table_name {
+      return self.run_transaction_statement("COMMIT");
+    }
+
+    // Swap the staging table in. A single RENAME TABLE is atomic, so readers see either the old
+    // table or the new one.
+    let replaced_table_name = format!("{}{}", table_name, REPLACED_TABLE_SUFFIX);
+    try!(self.drop_table(replaced_table_name.clone()));
+    let rename_string = if try!(self.table_exists(table_name.clone())) {
+      format!(
+        "RENAME TABLE {} TO {}, {} TO {}",
+        table_name,
+        replaced_table_name,
+        load_table_name,
+        table_name
+      )
     } else {
-      return Ok(());
+      format!("RENAME TABLE {} TO {}", load_table_name, table_name)
+    };
+    try!(self.run_transaction_statement(&rename_string));
+    self.drop_table(replaced_table_name)
+  }
+
+  fn abandon_table(&self, table_name: String, load_table_name: String) -> Result<()> {
+    trace!("abandon_table was called for: [ {} ]", table_name);
+    if load_table_name == table_name {
+      self.run_transaction_statement("ROLLBACK")
+    } else {
+      self.drop_table(load_table_name)
     }
   }
+
+  fn bulk_load(
+    &self,
+    table_name: String,
+    column_types: BTreeMap<String, String>,
+    column_names: Vec<String>,
+    data: Box<BufRead + Send>,
+  ) -> Result<()> {
+    trace!("bulk_load was called for table: {}", table_name);
+    // Get connection from the underlying pool.
+    let connection = self.get_connection();
+    if connection.is_err() {
+      return Err(ErrorKind::MysqlErr.into());
+    }
+    let mut connection = connection.unwrap();
+
+    // Typed columns are read into variables, and cast the same way they are for an INSERT.
+    let mut load_columns = Vec::new();
+    let mut set_clauses = Vec::new();
+    for column_name in column_names.iter() {
+      let escaped_column_name = self.escape_column_name(column_name);
+      let cast_as = get_cast_as(column_types.get(column_name).unwrap().to_owned(), self.db_type.clone());
+      if cast_as == "" {
+        load_columns.push(escaped_column_name);
+      } else {
+        load_columns.push(format!("@{}", escaped_column_name));
+        set_clauses.push(format!(
+          "{} = CAST(@{} AS {})",
+          escaped_column_name,
+          escaped_column_name,
+          cast_as
+        ));
+      }
+    }
+    // The file is read in the same character set the table was created with, so nothing is
+    // converted, or lost on the way in.
+    let mut load_string = format!(
+      "LOAD DATA LOCAL INFILE 'cdl' INTO TABLE {} CHARACTER SET {} \
+       FIELDS TERMINATED BY '\\t' ESCAPED BY '\\\\' LINES TERMINATED BY '\\n' ({})",
+      table_name,
+      MYSQL_CHARACTER_SET,
+      load_columns.join(",")
+    );
+    if !set_clauses.is_empty() {
+      load_string += &format!(" SET {}", set_clauses.join(","));
+    }
+    debug!("Bulk loading with: {}", load_string);
+
+    // The handler is kept by the connection, so it owns the file it streams, and is removed
+    // again once the load is done.
+    let reader = Mutex::new(Some(TsvReader::new(data, column_names.len())));
+    connection.set_local_infile_handler(Some(LocalInfileHandler::new(move |_, writer| {
+      match reader.lock().unwrap().take() {
+        Some(mut reader) => {
+          try!(io::copy(&mut reader, &mut *writer));
+          writer.flush()
+        }
+        None => Err(io::Error::new(io::ErrorKind::Other, "The file was already loaded")),
+      }
+    })));
+    let result = connection.query(&load_string).map(|result| result.affected_rows());
+    connection.set_local_infile_handler(None);
+
+    if result.is_err() {
+      error!("bulk_load err");
+      error!("{:?}", result.err().unwrap());
+      return Err(ErrorKind::MysqlErr.into());
+    }
+    debug!("Bulk loaded {} rows into: {}", result.unwrap(), table_name);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Cursor;
+
+  /// Reads a whole file through a TSV Reader.
+  ///
+  /// * `data` - The decompressed file.
+  /// * `column_count` - The number of columns being loaded.
+  fn read_tsv(data: &str, column_count: usize) -> String {
+    let mut normalized = String::new();
+    TsvReader::new(Cursor::new(data.as_bytes()), column_count)
+      .read_to_string(&mut normalized)
+      .expect("Failed to read the TSV");
+    normalized
+  }
+
+  /// Creates a client that never connects, for building statements with.
+  ///
+  /// Statements are built the same no matter the connection, only the database type matters.
+  ///
+  /// * `db_type` - The type of database to build statements for.
+  /// * `max_statement_bytes` - The most bytes a single statement can be.
+  #[cfg(feature = "postgres_compat")]
+  fn statement_client(db_type: DatabaseType, max_statement_bytes: usize) -> DatabaseClient<PostgresConnectionManager> {
+    let manager = PostgresConnectionManager::new("postgres://localhost/canvas_data_loader", TlsMode::None)
+      .expect("Failed to create the connection manager");
+    DatabaseClient {
+      db_type: db_type,
+      underlying_pool: Pool::builder().min_idle(Some(0)).build_unchecked(manager),
+      batch_size: 1000,
+      max_statement_bytes: max_statement_bytes,
+      max_concurrent_tables: 1,
+      can_bulk_load: true,
+      pinned_connection: None,
+    }
+  }
+
+  #[test]
+  fn tsv_reader_escapes_backslashes_except_nulls() {
+    assert_eq!(read_tsv("C:\\dir\t\\N\ta\\tb\n", 3), "C:\\\\dir\t\\N\ta\\\\tb\n");
+  }
+
+  #[test]
+  fn tsv_reader_only_treats_whole_values_as_nulls() {
+    assert_eq!(read_tsv("\\Nope\t\\N\n", 2), "\\\\Nope\t\\N\n");
+  }
+
+  #[test]
+  fn tsv_reader_rejects_lines_with_the_wrong_number_of_columns() {
+    let mut normalized = String::new();
+    let short = TsvReader::new(Cursor::new("1\t2\n".as_bytes()), 3).read_to_string(&mut normalized);
+    assert_eq!(short.map_err(|err| err.kind()), Err(io::ErrorKind::InvalidData));
+    let long = TsvReader::new(Cursor::new("1\t2\t3\t4\n".as_bytes()), 3).read_to_string(&mut normalized);
+    assert_eq!(long.map_err(|err| err.kind()), Err(io::ErrorKind::InvalidData));
+  }
+
+  #[test]
+  fn tsv_reader_ends_every_line_the_same() {
+    assert_eq!(read_tsv("1\t2\r\n3\t4", 2), "1\t2\n3\t4\n");
+  }
+
+  #[test]
+  #[cfg(feature = "postgres_compat")]
+  fn format_value_stores_the_same_value_as_a_bulk_load() {
+    let client = statement_client(DatabaseType::Psql, POSTGRES_MAX_STATEMENT_BYTES);
+    // A bulk load stores `it's "C:\dir"` for this line, so an insert has to as well.
+    assert_eq!(read_tsv("it's \"C:\\dir\"\n", 1), "it's \"C:\\\\dir\"\n");
+    assert_eq!(
+      client.format_value("varchar", &Some("it's \"C:\\dir\"".to_owned())),
+      "E'it''s \"C:\\\\dir\"'"
+    );
+    assert_eq!(client.format_value("bigint", &Some("12".to_owned())), "E'12'::int8");
+    assert_eq!(client.format_value("varchar", &None), "NULL");
+  }
+
+  #[test]
+  #[cfg(feature = "postgres_compat")]
+  fn format_value_escapes_for_mysql() {
+    let client = statement_client(DatabaseType::Mysql, POSTGRES_MAX_STATEMENT_BYTES);
+    assert_eq!(
+      client.format_value("varchar", &Some("it's \"C:\\dir\"".to_owned())),
+      "'it''s \"C:\\\\dir\"'"
+    );
+    assert_eq!(client.format_value("bigint", &Some("12".to_owned())), "CAST('12' AS SIGNED)");
+  }
+
+  #[test]
+  #[cfg(feature = "postgres_compat")]
+  fn build_statements_uses_one_statement_when_values_fit() {
+    let client = statement_client(DatabaseType::Psql, 100);
+    let values = vec!["aa".to_owned(), "bb".to_owned(), "cc".to_owned()];
+    assert_eq!(client.build_statements("P(", ")", values), vec!["P(aa,bb,cc)".to_owned()]);
+  }
+
+  #[test]
+  #[cfg(feature = "postgres_compat")]
+  fn build_statements_splits_at_max_statement_bytes() {
+    let client = statement_client(DatabaseType::Psql, 10);
+    let values = vec!["aa".to_owned(), "bb".to_owned(), "cc".to_owned()];
+    let statements = client.build_statements("P(", ")", values);
+    assert_eq!(statements, vec!["P(aa,bb)".to_owned(), "P(cc)".to_owned()]);
+    assert!(statements.iter().all(|statement| statement.len() <= 10));
+  }
+
+  #[test]
+  #[cfg(feature = "postgres_compat")]
+  fn build_statements_still_sends_an_oversized_value() {
+    let client = statement_client(DatabaseType::Psql, 3);
+    let values = vec!["toolong".to_owned(), "x".to_owned()];
+    assert_eq!(
+      client.build_statements("P(", ")", values),
+      vec!["P(toolong)".to_owned(), "P(x)".to_owned()]
+    );
+  }
+
+  #[test]
+  #[cfg(feature = "postgres_compat")]
+  fn build_statements_builds_nothing_without_values() {
+    let client = statement_client(DatabaseType::Psql, 100);
+    assert!(client.build_statements("P(", ")", Vec::new()).is_empty());
+  }
+
+  #[test]
+  #[cfg(feature = "postgres_compat")]
+  fn build_delete_statements_escapes_the_column_name() {
+    let client = statement_client(DatabaseType::Psql, POSTGRES_MAX_STATEMENT_BYTES);
+    let mut column_types = BTreeMap::new();
+    column_types.insert("default".to_owned(), "varchar".to_owned());
+    assert_eq!(
+      client.build_delete_statements("t", &column_types, "default", vec!["a".to_owned()]),
+      vec!["DELETE FROM t WHERE _default IN (E'a')".to_owned()]
+    );
+  }
 }
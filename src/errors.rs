@@ -1,7 +1,9 @@
 //! Provides all errors for the cdl-runner crate.
 
 use glob;
+use rayon;
 use reqwest;
+use serde_json;
 use std::io;
 
 error_chain! {
@@ -12,6 +14,117 @@ error_chain! {
       display("Invalid Type: [ {} ] to convert to DB", the_type)
     }
 
+    InvalidApiUrl(the_url: String) {
+      description("The configured Canvas Data API URL is invalid!")
+      display("Invalid Canvas Data API URL: [ {} ]", the_url)
+    }
+
+    InvalidSetting(name: String, value: String) {
+      description("A setting has an invalid value!")
+      display("Invalid value: [ {} ] for setting: {}", value, name)
+    }
+
+    InvalidProxyUrl(the_url: String) {
+      description("The configured proxy URL is invalid!")
+      display("Invalid proxy URL: [ {} ]", the_url)
+    }
+
+    InvalidCaCertificate(path: String) {
+      description("The configured CA certificate couldn't be loaded!")
+      display("Couldn't load CA certificates from: [ {} ]", path)
+    }
+
+    ApiAuthErr(endpoint: String, status: u16, body: String, diagnostic: String) {
+      description("The Canvas Data API rejected our credentials!")
+      display(
+        "The Canvas Data API rejected our credentials for: [ {} ] with HTTP Status: {}. {} Response: {}",
+        endpoint,
+        status,
+        diagnostic,
+        body
+      )
+    }
+
+    ApiRequestErr(endpoint: String, status: u16, body: String) {
+      description("The Canvas Data API rejected our request!")
+      display("The Canvas Data API rejected our request for: [ {} ] with HTTP Status: {}. Response: {}", endpoint, status, body)
+    }
+
+    ApiUnavailableErr(endpoint: String, status: u16, body: String, retry_after: Option<u64>) {
+      description("The Canvas Data API is unavailable!")
+      display("The Canvas Data API is unavailable for: [ {} ] with HTTP Status: {}. Response: {}", endpoint, status, body)
+    }
+
+    TransientHttpStatus(status: u16, retry_after: Option<u64>) {
+      description("Received a transient HTTP Status!")
+      display("Received transient HTTP Status: [ {} ]", status)
+    }
+
+    IncompleteDownload(filename: String, actual_size: u64, expected_size: u64) {
+      description("Downloaded file isn't the expected size!")
+      display("Downloaded file: [ {} ] is {} bytes, expected {} bytes", filename, actual_size, expected_size)
+    }
+
+    CorruptDownload(filename: String) {
+      description("Downloaded file isn't valid gzip!")
+      display("Downloaded file: [ {} ] isn't valid gzip", filename)
+    }
+
+    DownloadInterrupted(filename: String, reason: String) {
+      description("Download was interrupted!")
+      display("Download of file: [ {} ] was interrupted: {}", filename, reason)
+    }
+
+    ExpiredFileUrl(filename: String) {
+      description("The signed URL for a file has expired!")
+      display("The signed URL for file: [ {} ] has expired", filename)
+    }
+
+    OfflineImporter(dump_id: String) {
+      description("An offline importer can't talk to the Canvas Data API!")
+      display("The importer for dump: [ {} ] is offline, and can't talk to the Canvas Data API", dump_id)
+    }
+
+    MissingDumpFile(path: String) {
+      description("A file listed for the dump is missing!")
+      display("The file: [ {} ] is listed for the dump, but doesn't exist", path)
+    }
+
+    ArchiveExists(path: String) {
+      description("The dump has already been archived!")
+      display("The archive: [ {} ] already exists, and won't be replaced", path)
+    }
+
+    SchemaVersionMismatch(expected: String, found: String) {
+      description("The schema version doesn't match the dump!")
+      display("The dump is on schema version: [ {} ], but the schema is version: [ {} ]", expected, found)
+    }
+
+    ColumnCountMismatch(table_name: String, expected: usize, found: usize) {
+      description("A line doesn't have the columns of its table!")
+      display("A line for table: [ {} ] has {} columns, expected {} columns", table_name, found, expected)
+    }
+
+    DapNotConfigured {
+      description("Canvas Data 2 (DAP) isn't configured!")
+      display("Canvas Data 2 (DAP) isn't configured, add a [dap] section with a client_id, and client_secret")
+    }
+
+    DapJobFailed(job_id: String, status: String) {
+      description("A DAP query job failed!")
+      display("The DAP query job: [ {} ] failed with status: [ {} ]", job_id, status)
+    }
+
+    DapUnsupportedKey(table: String, key_columns: Vec<String>) {
+      description("A DAP table doesn't have a single key column!")
+      display("The DAP table: [ {} ] needs a single key column, but has: {:?}", table, key_columns)
+    }
+
+    UnsupportedDatabaseType(database_type: String) {
+      description("No support for the configured database type is compiled in!")
+      display("No support for the database type: [ {} ] is compiled in", database_type)
+    }
+
     PostgresErr {
       description("Underlying postgres error!")
       display("Underlying postgres error!")
@@ -33,6 +146,8 @@ error_chain! {
     HttpError(reqwest::Error);
     HttpUrlError(reqwest::UrlError);
     Ioerror(io::Error);
+    JsonError(serde_json::Error);
+    ThreadPoolError(rayon::ThreadPoolBuildError);
   }
 
 }
@@ -1,15 +1,20 @@
 //! Actually imports the data into a database.
 
-use api_client::{CanvasDataApiClient, TableDefinition};
+use api_client::{BasicFile, CanvasDataApiClient, DownloadedTable, SchemaDefinition, TableDefinition};
 use db_client::ImportDatabaseAdapter;
 use errors::*;
 use flate2::read::GzDecoder;
 use glob::glob;
-use rayon::prelude::*;
-use std::collections::BTreeMap;
+use rayon;
+use rocksdb::DB;
+use schema_diff::{diff_schemas, SchemaChange};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
+use std::io::BufReader;
 use std::io::prelude::*;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use type_converter::convert_type_for_db;
 
 lazy_static! {
@@ -48,22 +53,104 @@ lazy_static! {
   ];
 }
 
+/// Runs something for every item of an iterator, on up to `concurrency` threads at once.
+///
+/// Items are only taken from the iterator as threads free up, so an iterator that's bounded,
+/// like a download in progress, isn't drained any faster than its items are handled. No more
+/// items are taken once one fails, and the first failure is returned.
+///
+/// * `items` - The items to handle.
+/// * `concurrency` - The most items to handle at once.
+/// * `handle` - Handles a single item.
+fn try_for_each_concurrently<I, F>(items: I, concurrency: usize, handle: F) -> Result<()>
+where
+  I: Iterator + Send,
+  F: Fn(I::Item) -> Result<()> + Sync,
+{
+  let items = Mutex::new(items);
+  let first_err: Mutex<Option<Error>> = Mutex::new(None);
+
+  {
+    let items = &items;
+    let first_err = &first_err;
+    let handle = &handle;
+    // Each worker keeps taking items until there are none left, so no worker ever waits on
+    // another, no matter how few threads the pool has.
+    rayon::scope(|scope| {
+      for _ in 0..cmp::max(concurrency, 1) {
+        scope.spawn(move |_| loop {
+          if first_err.lock().expect("First error lock was poisoned!").is_some() {
+            return;
+          }
+          let item = match items.lock().expect("Items lock was poisoned!").next() {
+            Some(item) => item,
+            None => return,
+          };
+          if let Err(err) = handle(item) {
+            let mut first_err = first_err.lock().expect("First error lock was poisoned!");
+            if first_err.is_none() {
+              *first_err = Some(err);
+            }
+          }
+        });
+      }
+    });
+  }
+
+  match first_err.into_inner().expect("First error lock was poisoned!") {
+    Some(err) => Err(err),
+    None => Ok(()),
+  }
+}
+
+/// Forgets the file hashes recorded for a table, so it's never skipped as unchanged.
+///
+/// Anything that replaces, or changes a table outside of a dump import has to forget them, since
+/// the table no longer holds what the recorded files did.
+///
+/// * `whiskey` - The state store.
+/// * `table_name` - The table that's changing.
+pub fn forget_table_hashes(whiskey: &DB, table_name: &str) {
+  let _ = whiskey.delete(get_table_hashes_key(table_name).as_bytes());
+}
+
+/// Gets the key a table's file hashes are recorded under in the state store.
+///
+/// * `table_name` - The table the hashes are for.
+fn get_table_hashes_key(table_name: &str) -> String {
+  format!("table_hashes_{}", table_name)
+}
+
 /// The Root Importer Object.
 pub struct Importer<T: ImportDatabaseAdapter> {
-  /// The Canvas Data API Client.
-  api_client: CanvasDataApiClient,
+  /// The Canvas Data API Client, if this importer is allowed to talk to the API.
+  api_client: Option<CanvasDataApiClient>,
   /// The Dump ID to process.
   dump_id: String,
   /// The location of where to save stuff.
   save_location: String,
+  /// The Schema Definition of the dump being imported.
+  schema: SchemaDefinition,
   /// The Importing Database Adapter.
   db_adapter: T,
+  /// The state store, used to skip tables that haven't changed since they were last imported, and
+  /// to forget about tables that are replaced by other means.
+  whiskey: Option<Arc<DB>>,
 }
 unsafe impl<T: ImportDatabaseAdapter> Send for Importer<T> {}
 unsafe impl<T: ImportDatabaseAdapter> Sync for Importer<T> {}
 
+/// What happened to the tables of a dump when it was imported.
+#[derive(Clone, Debug, Default)]
+pub struct ImportReport {
+  /// The tables that were imported.
+  pub imported_tables: Vec<String>,
+  /// The tables that were skipped, because their files hadn't changed since they were last imported.
+  pub skipped_tables: Vec<String>,
+}
+
 /// A representation of the filenaame.
-struct FileNameSplit {
+pub struct FileNameSplit {
   /// The Table name of this file.
   pub table_name: String,
   /// The part of the internal shard for this file.
@@ -107,12 +194,80 @@ impl<T: ImportDatabaseAdapter> Importer<T> {
   /// * `db_adapter` - The Database Adapter to Import Into.
   /// * `dump_id` - The Dump ID to import.
   /// * `save_location` - The Save location.
-  pub fn new(api_client: CanvasDataApiClient, db_adapter: T, dump_id: String, save_location: String) -> Self {
+  /// * `schema` - The Schema Definition the dump was created with.
+  pub fn new(
+    api_client: CanvasDataApiClient,
+    db_adapter: T,
+    dump_id: String,
+    save_location: String,
+    schema: SchemaDefinition,
+  ) -> Self {
     Importer {
-      api_client: api_client,
+      api_client: Some(api_client),
       dump_id: dump_id,
       save_location: save_location,
+      schema: schema,
       db_adapter: db_adapter,
+      whiskey: None,
+    }
+  }
+
+  /// Creates a new Importer for a dump whose files were copied somewhere by hand.
+  ///
+  /// An offline importer never talks to the Canvas Data API, so it can only import files that
+  /// are already on disk.
+  ///
+  /// * `db_adapter` - The Database Adapter to Import Into.
+  /// * `dump_id` - The Dump ID to import.
+  /// * `directory` - The directory the dump's files were copied to.
+  /// * `schema` - The Schema Definition the dump was created with.
+  pub fn new_offline(db_adapter: T, dump_id: String, directory: String, schema: SchemaDefinition) -> Self {
+    Importer {
+      api_client: None,
+      dump_id: dump_id,
+      save_location: directory,
+      schema: schema,
+      db_adapter: db_adapter,
+      whiskey: None,
+    }
+  }
+
+  /// Sets the state store, so tables whose files haven't changed since they were last imported
+  /// are skipped, and tables imported by any other means aren't skipped afterwards.
+  ///
+  /// * `whiskey` - The state store.
+  pub fn set_state_store(&mut self, whiskey: Arc<DB>) {
+    self.whiskey = Some(whiskey);
+  }
+
+  /// Gets the key a table's file hashes are recorded under in the state store, and what they
+  /// are for this dump.
+  ///
+  /// The hashes are recorded along with the schema version, so a table is never skipped when
+  /// the schema changed.
+  ///
+  /// * `table_name` - The table the files are for.
+  /// * `paths` - The table's files.
+  fn get_table_hashes(&self, table_name: &str, paths: &[PathBuf]) -> (String, String) {
+    let hashes: BTreeSet<String> = paths
+      .iter()
+      .filter_map(|path| path.file_name().and_then(|file_name| file_name.to_str()))
+      .filter_map(|file_name| FileNameSplit::new(file_name.to_owned()))
+      .map(|file_name_split| file_name_split.hash_part)
+      .collect();
+    let hashes: Vec<String> = hashes.into_iter().collect();
+
+    (
+      get_table_hashes_key(table_name),
+      format!("{}:{}", self.schema.version, hashes.join(",")),
+    )
+  }
+
+  /// Gets the API Client, failing for offline importers.
+  fn get_api_client(&self) -> Result<&CanvasDataApiClient> {
+    match self.api_client {
+      Some(ref api_client) => Ok(api_client),
+      None => Err(ErrorKind::OfflineImporter(self.dump_id.clone()).into()),
     }
   }
 
@@ -186,222 +341,526 @@ impl<T: ImportDatabaseAdapter> Importer<T> {
   }
 
   /// Processes a Dump. Aka Imports it.
-  pub fn process(&self, is_all_volatile: bool) -> Result<()> {
+  ///
+  /// Each table is imported as soon as all of its files are downloaded, while the rest of the
+  /// dump keeps downloading in the background. Tables that finish downloading while another is
+  /// importing are imported at the same time. If there's a state store, tables that hold the
+  /// whole table in exactly the same files as when they were last imported are skipped.
+  pub fn process(&self, is_all_volatile: bool) -> Result<ImportReport> {
     trace!("Process Called for dump: {}", self.dump_id);
+    let report = Mutex::new(ImportReport::default());
 
-    // Download the Files for this dump.
-    try!(self.api_client.download_files_for_dump(
-      self.dump_id.clone(),
+    let download = try!(self.get_api_client()).download_dump_by_table(self.dump_id.clone());
+    try!(try_for_each_concurrently(
+      download,
+      self.db_adapter.get_max_concurrent_tables(),
+      |downloaded_table| self.process_table(try!(downloaded_table), is_all_volatile, &report),
     ));
 
-    // Glob to find downloaded files.
-    let saved_location_glob = format!("{}/{}/*.gz", &self.save_location, &self.dump_id);
-    let mut collected: Vec<_> = try!(glob(&saved_location_glob)).collect();
-
-    // Keep a seperate have failed for our iterator, and the tables we've already dropped.
-    // Don't want to drop a table multiple times.
-    let has_failed = AtomicBool::from(false);
-
-    // Drop tables first if first.
-    collected.iter_mut().map(|entry| {
-      // If we've already failed, skip. Don't try to keep importing.
-      if has_failed.load(Ordering::Relaxed) {
-        trace!("Skipping Entry: {:?} , due to failing", entry);
-        return;
-      }
+    Ok(report.into_inner().expect("Import report lock was poisoned!"))
+  }
 
-      if let &mut Ok(ref mut path) = entry {
-        let path_frd = path;
-        let file_name = path_frd.file_name().unwrap().to_str().unwrap().to_owned();
-        let file_name_split = FileNameSplit::new(file_name).unwrap();
-
-        if VOLATILE_TABLES.contains(&file_name_split.table_name) || is_all_volatile {
-          let drop_res = self.db_adapter.drop_table(file_name_split.table_name);
-          if drop_res.is_err() {
-                error!("process -> is_volatile -> drop_res -> is_err");
-                error!("{:?}", drop_res.err().unwrap());
-                has_failed.store(true, Ordering::Relaxed);
-                return;
+  /// Imports a single table of a dump once it's downloaded, unless it's unchanged.
+  ///
+  /// * `downloaded_table` - The table to import.
+  /// * `is_all_volatile` - Whether to treat every table as volatile.
+  /// * `report` - The report to add the table to.
+  fn process_table(
+    &self,
+    downloaded_table: DownloadedTable,
+    is_all_volatile: bool,
+    report: &Mutex<ImportReport>,
+  ) -> Result<()> {
+    let table_name = downloaded_table.table_name.clone();
+    let (hashes_key, hashes) = self.get_table_hashes(&table_name, &downloaded_table.paths);
+
+    if let Some(ref whiskey) = self.whiskey {
+      if !downloaded_table.partial {
+        if let Ok(Some(last_hashes)) = whiskey.get(hashes_key.as_bytes()) {
+          // A table that was dropped since it was recorded has to be imported again.
+          if last_hashes.to_utf8() == Some(hashes.as_str()) && try!(self.db_adapter.table_exists(table_name.clone())) {
+            info!("Skipping unchanged table: {} from dump: {}", table_name, self.dump_id);
+            report.lock().expect("Import report lock was poisoned!").skipped_tables.push(table_name);
+            return Ok(());
           }
         }
       }
-    }).count();
-
-    let _: Vec<_> = collected
-      .par_iter_mut()
-      .map(|entry| {
-        // If we've already failed, skip. Don't try to keep importing.
-        if has_failed.load(Ordering::Relaxed) {
-          trace!("Skipping Entry: {:?} , due to failing", entry);
-          return;
+    }
+
+    info!("Importing table: {} from dump: {}", table_name, self.dump_id);
+    try!(self.import_files(&downloaded_table.paths, &self.schema, is_all_volatile, true));
+
+    if let Some(ref whiskey) = self.whiskey {
+      if !downloaded_table.partial {
+        let _ = whiskey.put(hashes_key.as_bytes(), hashes.as_bytes());
+      }
+    }
+    report.lock().expect("Import report lock was poisoned!").imported_tables.push(table_name);
+    Ok(())
+  }
+
+  /// Imports every file that has already been downloaded for this dump.
+  ///
+  /// For the sync file set the "dump id" is the sync directory under the save location.
+  pub fn import_downloaded(&self, is_all_volatile: bool) -> Result<()> {
+    self.import_directory(&self.dump_id, &self.schema, is_all_volatile, true)
+  }
+
+  /// Imports a dump from files that were copied into the offline importer's directory.
+  ///
+  /// Every file in the saved file listing has to be in the directory, files in the directory that
+  /// aren't in the listing are ignored.
+  ///
+  /// * `files` - The dump's files, from its saved file listing.
+  /// * `is_all_volatile` - Whether to treat every table as volatile.
+  pub fn import_offline(&self, files: &[BasicFile], is_all_volatile: bool) -> Result<()> {
+    trace!("Import Offline Called for dump: {}", self.dump_id);
+
+    let mut paths = Vec::new();
+    for file in files.iter() {
+      let path = Path::new(&self.save_location).join(&file.filename);
+      if !path.is_file() {
+        return Err(ErrorKind::MissingDumpFile(path.to_string_lossy().into_owned()).into());
+      }
+      paths.push(path);
+    }
+
+    self.import_files(&paths, &self.schema, is_all_volatile, true)
+  }
+
+  /// Imports the history of a single table, replacing whatever is currently in the table.
+  ///
+  /// Only the latest complete copy of the table, and the partial files that came after it are
+  /// imported. Tables that only ever have partial files, like `requests`, import every file.
+  ///
+  /// Each dump's files are imported with the schema that dump is on, and the table is migrated
+  /// between schema versions as it goes, ending up on this importer's schema.
+  ///
+  /// * `table_name` - The table to import the history of.
+  pub fn backfill_table(&self, table_name: &str) -> Result<()> {
+    trace!("Backfill Table Called for: {}", table_name);
+
+    let api_client = try!(self.get_api_client());
+    let mut history = try!(api_client.get_files_for_table(table_name)).history;
+    history.sort_by_key(|entry| entry.sequence);
+    if let Some(last_complete) = history.iter().rposition(|entry| !entry.partial) {
+      history.drain(..last_complete);
+    }
+    info!(
+      "Backfilling table: {} from {} dumps",
+      table_name,
+      history.len()
+    );
+
+    // The history doesn't say which schema each dump's files are on, so look it up from the dumps.
+    let dump_schema_versions: BTreeMap<String, String> = try!(api_client.get_dumps())
+      .into_iter()
+      .map(|dump| (dump.dump_id, dump.schema_version))
+      .collect();
+    let mut schemas: BTreeMap<String, SchemaDefinition> = BTreeMap::new();
+    let table_names: BTreeSet<String> = vec![table_name.to_owned()].into_iter().collect();
+    let mut previous_schema: Option<SchemaDefinition> = None;
+
+    for (pos, entry) in history.iter().enumerate() {
+      let schema_version = match dump_schema_versions.get(&entry.dump_id) {
+        Some(schema_version) => schema_version.clone(),
+        None => try!(api_client.get_files_for_dump(entry.dump_id.clone())).schema_version,
+      };
+      if !schemas.contains_key(&schema_version) {
+        let schema = try!(api_client.get_schema(&schema_version));
+        schemas.insert(schema_version.clone(), schema);
+      }
+      let schema = schemas[&schema_version].clone();
+      debug!("Dump: {} of table: {} is on schema version: {}", entry.dump_id, table_name, schema.version);
+
+      let directory = try!(api_client.download_table_history(table_name, entry));
+      // The first set of files rebuilds the table from scratch, the rest are layered on top, once
+      // the table has the columns of the schema they're on.
+      match previous_schema {
+        None => try!(self.import_directory(&directory, &schema, true, true)),
+        Some(ref previous_schema) => {
+          try!(self.migrate_tables(previous_schema, &schema, &table_names));
+          try!(self.import_directory(&directory, &schema, false, false));
         }
+      }
+      previous_schema = Some(schema);
+    }
 
-        // If we have a path of a downloaded file.
-        if let &mut Ok(ref mut path) = entry {
-          // Get the filename of the downloaded file, and parse it since filenames are determinsitic.
-          trace!("Got Path");
-          let path_frd = path.clone();
-          let file_name = path_frd.file_name().unwrap().to_str().unwrap().to_owned();
-          let file_name_split = FileNameSplit::new(file_name).unwrap();
-          trace!("Post Split!");
-
-          // Get the table definition for the downloaded table we're looking at.
-          let table_def = self.api_client.get_table_definition(
-            file_name_split.table_name.clone(),
-          );
-          if table_def.is_err() {
-            error!("process -> table_def -> is_err");
-            error!("{:?}", table_def.err().unwrap());
-            has_failed.store(true, Ordering::Relaxed);
-            return;
-          }
-          let table_def = table_def.unwrap().unwrap();
-          let is_volatile_table = VOLATILE_TABLES.contains(&file_name_split.table_name) || is_all_volatile;
-
-          // Get the columns for our table.
-          let (column_names, column_defs) = self.get_table_info_from_def(table_def);
-          trace!("Post Table Def!");
-
-          // Open up the file for readaing.
-          let file = File::open(path_frd);
-          if file.is_err() {
-            error!("process -> file -> is_err");
-            error!("{:?}", file.err().unwrap());
-            has_failed.store(true, Ordering::Relaxed);
-            return;
-          }
-          let mut file = file.unwrap();
-          trace!("Post File Open");
-
-          // Read the entire file into a buffer.
-          // TODO: Maybe oneday switch to a buffered reader?
-          let mut buffer = Vec::new();
-          let res = file.read_to_end(&mut buffer);
-          if res.is_err() {
-            error!("process -> res -> is_err");
-            error!("{:?}", res.err().unwrap());
-            has_failed.store(true, Ordering::Relaxed);
-            return;
-          }
-          trace!("Post Reader");
-
-          // Uncompress the file.
-          let mut decoder = GzDecoder::new(buffer.as_slice());
-          trace!("Post Decoder Init");
-          let mut finalized_string = String::new();
-          let decode_res = decoder.read_to_string(&mut finalized_string);
-          if decode_res.is_err() {
-            error!("prcoess -> decode_res -> is_err");
-            error!("{:?}", decode_res.err().unwrap());
-            has_failed.store(true, Ordering::Relaxed);
-            return;
-          }
-          trace!("Post Decode to STR");
-          debug!("Decoded String: \n {:?}", finalized_string);
-
-          // Create the table if it doesn't exist.
-          let create_res = self.db_adapter.create_table(
-            file_name_split.table_name.clone(),
-            column_defs.clone(),
-          );
-          if create_res.is_err() {
-            error!("prcoess -> create_res -> is_err");
-            error!("{:?}", create_res.err().unwrap());
-            has_failed.store(true, Ordering::Relaxed);
-            return;
-          }
-          trace!("Post create table");
-
-          // For each line in this file.
-          for line in finalized_string.lines() {
-            trace!("Processing line: [ {:?} ]", line);
-            let mut columns = BTreeMap::new();
-            // Split by tabs, gather all columns.
-            let split_up_tsv_line: Vec<_> = line.split("\t").collect();
-            for (pos, name) in column_names.iter().enumerate() {
-              let mut split_up_line = Some(split_up_tsv_line[pos].to_owned());
-              if split_up_line.clone().unwrap().as_str() == "\\N" {
-                split_up_line = None
-              }
-              columns.insert(name.to_owned(), split_up_line);
-            }
+    match previous_schema {
+      Some(ref previous_schema) => self.migrate_tables(previous_schema, &self.schema, &table_names),
+      None => Ok(()),
+    }
+  }
 
-            trace!("Inserting Columns: [ {:?} ]", columns);
-
-            if is_volatile_table {
-              // If we're volatile don't check if it exists already, just insert.
-              trace!("Is volatile table, performing insert");
-              let ins_res = self.db_adapter.insert_record(
-                file_name_split.table_name.clone(),
-                column_defs.clone(),
-                columns,
-              );
-              if ins_res.is_err() {
-                error!("process -> for line in finalized_string -> is_volatile -> ins_res -> is_err");
-                error!("{:?}", ins_res.err().unwrap());
-                has_failed.store(true, Ordering::Relaxed);
-                return;
-              }
-            } else {
-              // Perform a diff if we're not volatile.
-              trace!("Is not volatile performing diff.");
-
-              // Get the ID to diff by.
-              let id_like_column = self.get_id_like_column_from_columns(file_name_split.table_name.clone(), &columns);
-              if id_like_column.is_none() {
-                error!("Failed to find table id like column!");
-                has_failed.store(true, Ordering::Relaxed);
-                return;
-              }
-              let id_like_column = id_like_column.unwrap();
-              let id_like_value = columns
-                .get(&id_like_column)
-                .unwrap()
-                .clone()
-                .unwrap()
-                .to_owned();
-              trace!("Performing deletion request for id like column");
-              // Send delete request for that ID. on first time seeing this will be no op due to WHERE Clause.
-              let del_res = self.db_adapter.drop_record(
-                file_name_split.table_name.clone(),
-                column_defs.clone(),
-                id_like_column,
-                id_like_value,
-              );
-              if del_res.is_err() {
-                error!("Failed to drop column!");
-                has_failed.store(true, Ordering::Relaxed);
-                return;
-              }
-
-              // Insert the column to overwrite.
-              trace!("Performing insert");
-              let ins_res = self.db_adapter.insert_record(
-                file_name_split.table_name.clone(),
-                column_defs.clone(),
-                columns,
-              );
-              if ins_res.is_err() {
-                error!("process -> for line in finalized_string -> !is_volatile -> ins_res -> is_err");
-                error!("{:?}", ins_res.err().unwrap());
-                has_failed.store(true, Ordering::Relaxed);
-                return;
-              }
-            }
-            trace!("Imported Line.");
-          }
+  /// Imports every file that has been downloaded to a directory under the save location.
+  ///
+  /// * `directory` - The directory under the save location to import.
+  /// * `schema` - The schema the files are on.
+  /// * `is_all_volatile` - Whether to treat every table as volatile.
+  /// * `should_drop_volatile` - Whether to drop volatile tables before importing them.
+  fn import_directory(
+    &self,
+    directory: &str,
+    schema: &SchemaDefinition,
+    is_all_volatile: bool,
+    should_drop_volatile: bool,
+  ) -> Result<()> {
+    trace!("Import Directory Called for: {}", directory);
+
+    // Glob to find downloaded files.
+    let saved_location_glob = format!("{}/{}/*.gz", &self.save_location, directory);
+    let collected: Vec<PathBuf> = try!(glob(&saved_location_glob))
+      .filter_map(|entry| entry.ok())
+      .collect();
+
+    self.import_files(&collected, schema, is_all_volatile, should_drop_volatile)
+  }
+
+  /// Imports a list of files, parsing the table each one belongs to from its filename.
+  ///
+  /// Up to `max_concurrent_tables` tables are imported at once, but the files of a single table are
+  /// imported one after the other, so the whole table can be imported in a single transaction.
+  ///
+  /// * `paths` - The files to import.
+  /// * `schema` - The schema the files are on.
+  /// * `is_all_volatile` - Whether to treat every table as volatile.
+  /// * `should_drop_volatile` - Whether to drop volatile tables before importing them.
+  fn import_files(
+    &self,
+    paths: &[PathBuf],
+    schema: &SchemaDefinition,
+    is_all_volatile: bool,
+    should_drop_volatile: bool,
+  ) -> Result<()> {
+    // Group the files by the table they're for, since filenames are determinsitic.
+    let mut tables: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for path in paths.iter() {
+      let file_name = path.file_name().and_then(|file_name| file_name.to_str()).map(|file_name| file_name.to_owned());
+      match file_name.and_then(FileNameSplit::new) {
+        Some(file_name_split) => tables.entry(file_name_split.table_name).or_insert_with(Vec::new).push(path.clone()),
+        None => {
+          error!("Can't tell what table the file: {:?} is for", path);
+          return Err(ErrorKind::ImportErr.into());
+        }
+      }
+    }
+
+    // Each table holds a database connection while it's imported, so only import as many at
+    // once as there's room for.
+    let import_res = try_for_each_concurrently(
+      tables.iter(),
+      self.db_adapter.get_max_concurrent_tables(),
+      |(table_name, table_paths)| {
+        self
+          .import_table(table_name, table_paths, schema, is_all_volatile, should_drop_volatile)
+          .map_err(|err| {
+            error!("process -> import_res -> is_err for table: {}", table_name);
+            error!("{:?}", err);
+            err
+          })
+      },
+    );
+
+    debug!("Has Failed: {}", import_res.is_err());
+    import_res.chain_err(|| ErrorKind::ImportErr)
+  }
+
+  /// Imports every file of a single table, so that either all of them are seen, or none of them are.
+  ///
+  /// * `table_name` - The table the files are for.
+  /// * `paths` - The table's files.
+  /// * `schema` - The schema the files are on.
+  /// * `is_all_volatile` - Whether to treat every table as volatile.
+  /// * `should_drop_volatile` - Whether to drop the table before importing it if it's volatile.
+  fn import_table(
+    &self,
+    table_name: &str,
+    paths: &[PathBuf],
+    schema: &SchemaDefinition,
+    is_all_volatile: bool,
+    should_drop_volatile: bool,
+  ) -> Result<()> {
+    trace!("Import Table Called for: {}", table_name);
+
+    // The table is about to change, so whatever was recorded no longer describes it.
+    if let Some(ref whiskey) = self.whiskey {
+      forget_table_hashes(whiskey, table_name);
+    }
+
+    // Get the table definition for the table we're looking at.
+    let table_def = match schema.get_table_definition(table_name) {
+      Some(table_def) => table_def,
+      None => {
+        error!(
+          "No definition for table: {} in schema version: {}",
+          table_name,
+          schema.version
+        );
+        return Err(ErrorKind::ImportErr.into());
+      }
+    };
+    let is_volatile_table = VOLATILE_TABLES.contains(&table_name.to_owned()) || is_all_volatile;
+    let (column_names, column_defs) = self.get_table_info_from_def(table_def);
+
+    // Volatile tables, and tables that don't exist yet don't need merging, so they can be bulk loaded.
+    let should_bulk_load = self.db_adapter.supports_bulk_load() &&
+      (is_volatile_table || !try!(self.db_adapter.table_exists(table_name.to_owned())));
+
+    let (table_adapter, load_table_name) = try!(self.db_adapter.begin_table(
+      table_name.to_owned(),
+      column_defs.clone(),
+      should_drop_volatile && is_volatile_table,
+    ));
+
+    let mut import_res = Ok(());
+    for path in paths.iter() {
+      import_res = self.import_file(
+        &table_adapter,
+        &load_table_name,
+        path,
+        &column_names,
+        &column_defs,
+        is_volatile_table,
+        should_bulk_load,
+      );
+      if import_res.is_err() {
+        break;
+      }
+    }
+
+    match import_res {
+      Ok(()) => table_adapter.finish_table(table_name.to_owned(), load_table_name),
+      Err(err) => {
+        if let Err(abandon_err) = table_adapter.abandon_table(table_name.to_owned(), load_table_name) {
+          warn!("Failed to throw away the changes to table: {}", table_name);
+          warn!("{:?}", abandon_err);
+        }
+        Err(err)
+      }
+    }
+  }
+
+  /// Imports a single file into a table.
+  ///
+  /// * `db_adapter` - The Database Adapter the table is being imported with.
+  /// * `table_name` - The table to import into.
+  /// * `path` - The file to import.
+  /// * `column_names` - The names of the columns in the file, in order.
+  /// * `column_defs` - The types of the table's columns.
+  /// * `is_volatile_table` - Whether the table is volatile.
+  /// * `should_bulk_load` - Whether to bulk load the file, instead of merging it.
+  fn import_file(
+    &self,
+    db_adapter: &T,
+    table_name: &str,
+    path: &Path,
+    column_names: &[String],
+    column_defs: &BTreeMap<String, String>,
+    is_volatile_table: bool,
+    should_bulk_load: bool,
+  ) -> Result<()> {
+    trace!("Importing file: {:?} into: {}", path, table_name);
+
+    // Decompress the file as it's read, a line at a time, so only a bit of it is ever in memory.
+    let file = try!(File::open(path));
+    let reader = BufReader::new(GzDecoder::new(BufReader::new(file)));
+
+    if should_bulk_load {
+      trace!("Bulk loading table: {}", table_name);
+      return db_adapter.bulk_load(
+        table_name.to_owned(),
+        column_defs.clone(),
+        column_names.to_vec(),
+        Box::new(reader),
+      );
+    }
+
+    // Rows are gathered up, and sent to the database a batch at a time.
+    let batch_size = db_adapter.get_batch_size();
+    let mut batch = Vec::with_capacity(batch_size);
+
+    // For each line in this file.
+    for line in reader.lines() {
+      let line = try!(line);
+      trace!("Processing line: [ {:?} ]", line);
+      let mut columns = BTreeMap::new();
+      // Split by tabs, gather all columns.
+      let split_up_tsv_line: Vec<_> = line.split("\t").collect();
+      // Files are always imported with the schema they're on, so a line with a different number
+      // of columns is malformed, and guessing which columns it has would import the wrong values.
+      if split_up_tsv_line.len() != column_names.len() {
+        error!("Line: [ {:?} ] doesn't have the columns of table: {}", line, table_name);
+        return Err(
+          ErrorKind::ColumnCountMismatch(table_name.to_owned(), column_names.len(), split_up_tsv_line.len()).into(),
+        );
+      }
+      for (name, value) in column_names.iter().zip(split_up_tsv_line.into_iter()) {
+        let value = if value == "\\N" { None } else { Some(value.to_owned()) };
+        columns.insert(name.to_owned(), value);
+      }
+
+      trace!("Queueing Columns: [ {:?} ]", columns);
+      batch.push(columns);
+
+      if batch.len() >= batch_size {
+        try!(self.import_batch(db_adapter, table_name, column_defs, batch, is_volatile_table));
+        batch = Vec::with_capacity(batch_size);
+        trace!("Imported Batch.");
+      }
+    }
+
+    // Import whatever is left over at the end of the file.
+    if !batch.is_empty() {
+      try!(self.import_batch(db_adapter, table_name, column_defs, batch, is_volatile_table));
+    }
+    Ok(())
+  }
+
+  /// Imports a batch of rows from a single file into its table.
+  ///
+  /// Rows of volatile tables are just inserted. Otherwise the existing rows with the same IDs are
+  /// dropped first, and only the last row for each ID in the batch is inserted, the same as if
+  /// each row had replaced the one before it.
+  ///
+  /// * `db_adapter` - The Database Adapter the table is being imported with.
+  /// * `table_name` - The table the rows are for.
+  /// * `column_defs` - The types of the table's columns.
+  /// * `rows` - The rows to import.
+  /// * `is_volatile_table` - Whether the table is volatile.
+  fn import_batch(
+    &self,
+    db_adapter: &T,
+    table_name: &str,
+    column_defs: &BTreeMap<String, String>,
+    rows: Vec<BTreeMap<String, Option<String>>>,
+    is_volatile_table: bool,
+  ) -> Result<()> {
+    if is_volatile_table {
+      // If we're volatile don't check if it exists already, just insert.
+      trace!("Is volatile table, performing insert");
+      return db_adapter.insert_records(table_name.to_owned(), column_defs.clone(), rows);
+    }
+
+    // Perform a diff if we're not volatile. Every row of a table has the same columns, so the
+    // ID to diff by is the same for all of them.
+    trace!("Is not volatile performing diff.");
+    let id_like_column = match rows.first() {
+      Some(row) => self.get_id_like_column_from_columns(table_name.to_owned(), row),
+      None => return Ok(()),
+    };
+    let id_like_column = match id_like_column {
+      Some(id_like_column) => id_like_column,
+      None => {
+        error!("Failed to find table id like column for: {}", table_name);
+        return Err(ErrorKind::ImportErr.into());
+      }
+    };
+
+    // Only keep the last row for each ID.
+    let mut last_positions = BTreeMap::new();
+    for (pos, row) in rows.iter().enumerate() {
+      match row.get(&id_like_column).and_then(|value| value.clone()) {
+        Some(id_like_value) => {
+          last_positions.insert(id_like_value, pos);
+        }
+        None => {
+          error!("Row in: {} has no value for: {}", table_name, id_like_column);
+          return Err(ErrorKind::ImportErr.into());
         }
-      })
+      }
+    }
+    let kept_positions: BTreeSet<usize> = last_positions.values().cloned().collect();
+    let id_like_values: Vec<String> = last_positions.into_iter().map(|(value, _)| value).collect();
+    let rows: Vec<_> = rows
+      .into_iter()
+      .enumerate()
+      .filter(|&(pos, _)| kept_positions.contains(&pos))
+      .map(|(_, row)| row)
       .collect();
 
-    debug!("Has Failed: {}", has_failed.load(Ordering::Relaxed));
+    // Send delete request for those IDs. On first time seeing them will be no op due to WHERE Clause.
+    trace!("Performing deletion request for id like column");
+    try!(db_adapter.drop_records(
+      table_name.to_owned(),
+      column_defs.clone(),
+      id_like_column,
+      id_like_values,
+    ));
+
+    // Insert the rows to overwrite.
+    trace!("Performing insert");
+    db_adapter.insert_records(table_name.to_owned(), column_defs.clone(), rows)
+  }
 
-    if !has_failed.load(Ordering::Relaxed) {
-      trace!("Hasn't Failed");
-      Ok(())
+  /// Migrates the tables of an imported dump forward to a newer schema version.
+  ///
+  /// Adds columns that are new in the newer schema, and changes the type of columns whose type
+  /// has changed. Columns that were removed are left in place. Does nothing if the dump is already
+  /// on the newer schema version.
+  ///
+  /// * `latest_schema` - The schema version to migrate to.
+  pub fn migrate_to_schema(&self, latest_schema: &SchemaDefinition) -> Result<()> {
+    if self.schema.version == latest_schema.version {
+      return Ok(());
+    }
+    info!(
+      "Migrating dump: {} from schema version: {} to: {}",
+      self.dump_id,
+      self.schema.version,
+      latest_schema.version
+    );
+
+    // Only the tables that were in this dump are guaranteed to exist. An offline importer's files
+    // are right in its directory, instead of in a directory for the dump.
+    let saved_location_glob = if self.api_client.is_some() {
+      format!("{}/{}/*.gz", &self.save_location, &self.dump_id)
     } else {
-      trace!("Has Failed!");
-      Err(ErrorKind::ImportErr.into())
+      format!("{}/*.gz", &self.save_location)
+    };
+    let mut imported_tables = BTreeSet::new();
+    for entry in try!(glob(&saved_location_glob)) {
+      if let Ok(path) = entry {
+        let file_name = path.file_name().unwrap().to_str().unwrap().to_owned();
+        if let Some(file_name_split) = FileNameSplit::new(file_name) {
+          imported_tables.insert(file_name_split.table_name);
+        }
+      }
+    }
+
+    self.migrate_tables(&self.schema, latest_schema, &imported_tables)
+  }
+
+  /// Migrates tables from one schema version to another.
+  ///
+  /// * `from_schema` - The schema the tables are on.
+  /// * `to_schema` - The schema to migrate the tables to.
+  /// * `table_names` - The tables to migrate, any others in the schemas are left alone.
+  fn migrate_tables(
+    &self,
+    from_schema: &SchemaDefinition,
+    to_schema: &SchemaDefinition,
+    table_names: &BTreeSet<String>,
+  ) -> Result<()> {
+    if from_schema.version == to_schema.version {
+      return Ok(());
+    }
+
+    for change in diff_schemas(from_schema, to_schema) {
+      match change {
+        SchemaChange::ColumnAdded(ref table_name, ref column) if table_names.contains(table_name) => {
+          info!("Migrating: {}", change);
+          let column_type = try!(convert_type_for_db(column.db_type.clone(), self.db_adapter.get_db_type()));
+          try!(self.db_adapter.add_column(table_name.clone(), column.name.clone(), column_type));
+        }
+        SchemaChange::ColumnTypeChanged(ref table_name, _, ref column) if table_names.contains(table_name) => {
+          info!("Migrating: {}", change);
+          let column_type = try!(convert_type_for_db(column.db_type.clone(), self.db_adapter.get_db_type()));
+          try!(self.db_adapter.alter_column_type(table_name.clone(), column.name.clone(), column_type));
+        }
+        _ => debug!("Nothing to migrate for: {}", change),
+      }
     }
+
+    Ok(())
   }
 }
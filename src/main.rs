@@ -20,6 +20,7 @@ extern crate rocksdb;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
 extern crate tokio_core;
 
@@ -32,18 +33,33 @@ extern crate r2d2_postgres;
 extern crate mysql;
 
 pub mod api_client;
+pub mod bandwidth;
+pub mod dap_client;
+pub mod dap_importer;
 pub mod db_client;
 pub mod errors;
 pub mod importer;
+pub mod retention;
+pub mod schema_diff;
 pub mod settings;
 pub mod type_converter;
 
 #[cfg(feature = "mysql_compat")]
 pub mod mysql_pool;
 
-use db_client::DatabaseClient;
+use api_client::{CanvasDataApiClient, FilesInDumpResponse, SchemaDefinition, SYNC_DIRECTORY, TABLES_DIRECTORY};
+use dap_client::DapClient;
+use db_client::{DatabaseClient, ImportDatabaseAdapter};
+use errors::ErrorKind;
 use rocksdb::DB;
-use settings::DatabaseType;
+use serde::de::DeserializeOwned;
+use settings::{DatabaseType, OutOfDateStrategy, Settings};
+use std::env;
+use std::fs::File;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 
 #[cfg(feature = "postgres_compat")]
 use r2d2_postgres::PostgresConnectionManager;
@@ -57,12 +73,256 @@ fn main() {
 
   // Initalize Settings.
   let settings = settings::Settings::new();
-  let has_errord = false;
+
+  // Figure out which command to run, importing dumps by default.
+  let command = env::args().nth(1).unwrap_or("dumps".to_owned());
+  match command.as_str() {
+    "dumps" => import_dumps(&settings),
+    "sync" => import_sync(&settings),
+    "backfill" => {
+      match env::args().nth(2) {
+        Some(table_name) => backfill_table(&settings, table_name.to_lowercase()),
+        None => {
+          error!("The backfill command needs a table name: cdl-runner backfill <table_name>");
+          process::exit(1);
+        }
+      }
+    }
+    "offline" => {
+      match env::args().nth(2) {
+        Some(directory) => {
+          let schema_path = env::args().nth(3).unwrap_or(format!("{}/schema.json", directory));
+          let files_path = env::args().nth(4).unwrap_or(format!("{}/files.json", directory));
+          import_offline(&settings, directory, Some(schema_path), files_path, false)
+        }
+        None => {
+          error!("The offline command needs a directory: cdl-runner offline <directory> [schema.json] [files.json]");
+          process::exit(1);
+        }
+      }
+    }
+    "replay" => {
+      match env::args().nth(2) {
+        Some(dump_id) => {
+          // Replay the responses recorded in the dump's directory, with `record_api_responses`.
+          let directory = format!("{}/{}", settings.get_save_location(), dump_id);
+          let files_path = format!("{}/files.json", directory);
+          import_offline(&settings, directory, None, files_path, true)
+        }
+        None => {
+          error!("The replay command needs a dump id: cdl-runner replay <dump_id>");
+          process::exit(1);
+        }
+      }
+    }
+    "dap" => import_dap(&settings),
+    _ => {
+      error!("Unknown command: {}. Valid commands are: dumps, sync, backfill, offline, replay, dap", command);
+      process::exit(1);
+    }
+  }
+}
+
+/// Logs a fatal error, along with everything that caused it, and exits with a failing status.
+///
+/// * `context` - What we were doing when the error happened.
+/// * `err` - The error that happened.
+fn exit_with_error(context: &str, err: errors::Error) -> ! {
+  error!("{}: {}", context, err);
+  for cause in err.iter().skip(1) {
+    error!("Caused by: {}", cause);
+  }
+  process::exit(1);
+}
+
+/// Reads, and parses a JSON file saved from the Canvas Data API.
+///
+/// * `path` - The path of the JSON file.
+fn read_json_file<T: DeserializeOwned>(path: &str) -> errors::Result<T> {
+  let file = try!(File::open(path));
+  Ok(try!(serde_json::from_reader(file)))
+}
+
+/// Checks the state store for whether a dump has already been dealt with, and won't be imported.
+///
+/// * `whiskey` - The state store.
+/// * `dump_id` - The Dump ID to check.
+fn is_dump_processed(whiskey: &DB, dump_id: &str) -> bool {
+  match whiskey.get(format!("dump_processed_{}", dump_id).as_bytes()) {
+    Ok(Some(processed)) => {
+      match processed.to_utf8() {
+        Some("successful") | Some("out-of-date") | Some("refused") => true,
+        _ => false,
+      }
+    }
+    _ => false,
+  }
+}
+
+/// Waits for a background download of a dump to finish.
+///
+/// A failed prefetch isn't fatal, the dump's files are downloaded again when it's imported.
+///
+/// * `prefetch` - The Dump ID being prefetched, and its download thread.
+fn wait_for_prefetch(prefetch: Option<(String, JoinHandle<errors::Result<()>>)>) {
+  if let Some((dump_id, handle)) = prefetch {
+    match handle.join() {
+      Ok(Ok(_)) => debug!("Finished prefetching dump: {}", dump_id),
+      Ok(Err(err)) => warn!("Failed to prefetch dump: {}: {}", dump_id, err),
+      Err(_) => warn!("The prefetch of dump: {} panicked", dump_id),
+    }
+  }
+}
+
+/// Logs what happened to the tables of a dump when it was imported.
+///
+/// * `dump_id` - The Dump ID that was imported.
+/// * `report` - The report from the import.
+fn log_import_report(dump_id: &str, report: &importer::ImportReport) {
+  info!(
+    "Imported dump: {} with {} tables imported, and {} unchanged tables skipped",
+    dump_id,
+    report.imported_tables.len(),
+    report.skipped_tables.len()
+  );
+  if !report.skipped_tables.is_empty() {
+    info!("Skipped unchanged tables: {}", report.skipped_tables.join(", "));
+  }
+}
+
+/// What to do with a dump that's about to be imported.
+enum DumpPlan {
+  /// Import the dump with the schema it's on, and then migrate it to the latest schema.
+  Import(SchemaDefinition),
+  /// Don't import the dump, and mark it as processed with this state.
+  Skip(&'static str),
+}
+
+/// Decides how a dump is imported, based on the schema it's on, and the out of date strategy.
+///
+/// * `settings` - The settings to import with.
+/// * `dump_id` - The dump to import.
+/// * `dump_schema_version` - The schema version the dump is on.
+/// * `latest_schema` - The latest schema.
+/// * `get_schema` - Gets the definition of the dump's schema version, if it isn't the latest.
+fn plan_dump_import<F>(
+  settings: &Settings,
+  dump_id: &str,
+  dump_schema_version: &str,
+  latest_schema: &SchemaDefinition,
+  get_schema: F,
+) -> errors::Result<DumpPlan>
+where
+  F: FnOnce(&str) -> errors::Result<SchemaDefinition>,
+{
+  if latest_schema.version == dump_schema_version {
+    return Ok(DumpPlan::Import(latest_schema.clone()));
+  }
+
+  let out_of_date_strategy = try!(settings.get_out_of_date_strategy());
+  if out_of_date_strategy == OutOfDateStrategy::Skip {
+    info!(
+      "Skipping dump: {} since it's on schema version: {} instead of: {}",
+      dump_id,
+      dump_schema_version,
+      latest_schema.version
+    );
+    return Ok(DumpPlan::Skip("out-of-date"));
+  }
+
+  let dump_schema = try!(get_schema(dump_schema_version));
+  if out_of_date_strategy == OutOfDateStrategy::Refuse {
+    error!(
+      "Refusing to import dump: {} since it's on schema version: {} instead of: {}. Changes:",
+      dump_id,
+      dump_schema_version,
+      latest_schema.version
+    );
+    for change in schema_diff::diff_schemas(&dump_schema, latest_schema) {
+      error!("  {}", change);
+    }
+    return Ok(DumpPlan::Skip("refused"));
+  }
+
+  info!(
+    "Importing dump: {} with its own schema version: {}",
+    dump_id,
+    dump_schema_version
+  );
+  Ok(DumpPlan::Import(dump_schema))
+}
+
+/// Gets whether every table of a dump should be treated as volatile.
+///
+/// If the schema changed since the last import, every table is volatile to ensure tables are up to date.
+///
+/// * `settings` - The settings to import with.
+/// * `whiskey` - The state store, with the last schema version processed.
+/// * `latest_schema` - The latest schema.
+fn get_is_all_volatile(settings: &Settings, whiskey: &DB, latest_schema: &SchemaDefinition) -> bool {
+  if settings.get_all_tables_volatile() {
+    return true;
+  }
+  match whiskey.get("last_version_processed".as_bytes()) {
+    Ok(Some(last_processed_schema)) => last_processed_schema.to_utf8() != Some(latest_schema.version.as_str()),
+    _ => false,
+  }
+}
+
+/// An import that can run against any database adapter.
+///
+/// Which adapter to use is only known from the settings at runtime, so each command describes its
+/// import with this, and `with_configured_adapter` runs it against the configured database.
+trait AdapterImport {
+  /// What the import returns.
+  type Output;
+
+  /// Runs the import with a specific database adapter.
+  ///
+  /// * `db_client` - The Database Adapter to import into.
+  fn import_with<T: ImportDatabaseAdapter>(self, db_client: T) -> Self::Output;
+}
+
+/// Connects to the configured database, and runs an import against it.
+///
+/// * `settings` - The settings to connect with.
+/// * `import` - The import to run.
+fn with_configured_adapter<I: AdapterImport>(settings: &Settings, import: I) -> errors::Result<I::Output> {
+  // If we have postgres compatability, and are configured for postgres, import into that.
+  if cfg!(feature = "postgres_compat") {
+    if settings.get_database_type() == DatabaseType::Psql {
+      info!("Connecting to the DB");
+      let db_client = try!(DatabaseClient::<PostgresConnectionManager>::new(settings));
+      return Ok(import.import_with(db_client));
+    }
+  }
+
+  // If we have mysql compatability, and are configured for mysql, import into that.
+  if cfg!(feature = "mysql_compat") {
+    if settings.get_database_type() == DatabaseType::Mysql {
+      info!("Connecting to the DB");
+      let db_client = try!(DatabaseClient::<MysqlConnectionManager>::new(settings));
+      return Ok(import.import_with(db_client));
+    }
+  }
+
+  Err(ErrorKind::UnsupportedDatabaseType(format!("{:?}", settings.get_database_type())).into())
+}
+
+/// Imports every dump that hasn't been imported yet, one after another.
+///
+/// * `settings` - The settings to import with.
+fn import_dumps(settings: &Settings) {
+  let mut has_errord = false;
   info!("Setting up API Client...");
 
   // Get the dump listing, and setup some variables for iteration.
-  let api_client = api_client::CanvasDataApiClient::new(&settings);
-  let mut dumps = api_client.get_dumps().expect("Failed to get List of Dumps");
+  let api_client = api_client::CanvasDataApiClient::new(settings)
+    .unwrap_or_else(|err| exit_with_error("Failed to setup API Client", err));
+  let mut dumps: Vec<_> = api_client
+    .iter_dumps()
+    .collect::<errors::Result<_>>()
+    .unwrap_or_else(|err| exit_with_error("Failed to get List of Dumps", err));
   dumps.sort_by(|dump_one, dump_two| {
     dump_one.created_at.cmp(&dump_two.created_at)
   });
@@ -70,24 +330,25 @@ fn main() {
   let only_final_dump = settings.get_should_only_load_final();
   let mut current_dumps_pos = 0;
   debug!("{:?}", dumps);
+  // The dumps that could be prefetched, in import order.
+  let upcoming_dumps: Vec<_> = dumps
+    .iter()
+    .filter(|dump| dump.finished)
+    .map(|dump| dump.dump_id.clone())
+    .collect();
+  let all_dump_ids: Vec<_> = dumps.iter().map(|dump| dump.dump_id.clone()).collect();
+  let should_prefetch = settings.get_should_prefetch_next_dump() && !only_final_dump;
+  let mut prefetch: Option<(String, JoinHandle<errors::Result<()>>)> = None;
 
   // Connect to the local KV Store.
   info!("Connecting to RocksDB Store....");
-  let whiskey = DB::open_default(settings.get_rocksdb_location()).expect("Failed to open RocksDB");
+  let whiskey = Arc::new(DB::open_default(settings.get_rocksdb_location()).expect("Failed to open RocksDB"));
 
   // Get the latest schema.
-  let latest_schema = api_client.get_latest_schema().expect(
-    "Failed to fetch latest schema!",
-  );
-  let mut last_processed_schema = latest_schema.version.clone();
-  let last_processed_schema_res = whiskey.get("last_version_processed".as_bytes());
-  if let Ok(new_last_processed_schema_opt) =  last_processed_schema_res {
-    if let Some(new_last_processed_schema_bytes) = new_last_processed_schema_opt {
-      if let Some(new_last_processed_schema) = new_last_processed_schema_bytes.to_utf8() {
-        last_processed_schema = new_last_processed_schema.to_owned();
-      }
-    }
-  }
+  let latest_schema = api_client
+    .get_latest_schema()
+    .unwrap_or_else(|err| exit_with_error("Failed to fetch latest schema", err));
+  let is_all_volatile = get_is_all_volatile(settings, &whiskey, &latest_schema);
 
   let _: Vec<_> = dumps
     .into_iter()
@@ -130,7 +391,7 @@ fn main() {
         let potentially_processed = potentially_processed.to_utf8();
         if potentially_processed.is_some() {
           let processed = potentially_processed.unwrap();
-          if processed == "successful" || processed == "out-of-date" {
+          if processed == "successful" || processed == "out-of-date" || processed == "refused" {
             info!("Skipping already processed dump: {}", dump.dump_id);
             return Ok(());
           }
@@ -138,19 +399,47 @@ fn main() {
       }
 
       // Check if the dump queued for import is the correct schema version.
-      if latest_schema.version != dump.schema_version {
-        let _ = whiskey.put(
-          format!("dump_processed_{}", dump.dump_id.clone()).as_bytes(),
-          b"out-of-date",
-        );
-        return Ok(());
-      }
+      let plan = plan_dump_import(
+        settings,
+        &dump.dump_id,
+        &dump.schema_version,
+        &latest_schema,
+        |version| api_client.get_schema(version),
+      );
+      let dump_schema = match plan {
+        Ok(DumpPlan::Import(dump_schema)) => dump_schema,
+        Ok(DumpPlan::Skip(state)) => {
+          let _ = whiskey.put(
+            format!("dump_processed_{}", dump.dump_id.clone()).as_bytes(),
+            state.as_bytes(),
+          );
+          return Ok(());
+        }
+        Err(err) => {
+          error!(
+            "Failed to decide how to import dump: {} on schema version: {}: {}",
+            dump.dump_id,
+            dump.schema_version,
+            err
+          );
+          has_errord = true;
+          return Err(());
+        }
+      };
 
       // Get the files for this particular dump.
       let files_in_dump = api_client.get_files_for_dump(dump.dump_id.clone());
+      if let Err(err) = api_client.save_recorded_responses(&dump.dump_id) {
+        error!("Failed to save the recorded API responses for dump: {}: {}", dump.dump_id, err);
+      }
       if files_in_dump.is_err() {
-        info!("Failed to list files for dump. Skipping...");
-        return Ok(());
+        error!(
+          "Failed to list files for dump: {}: {}",
+          dump.dump_id,
+          files_in_dump.err().unwrap()
+        );
+        has_errord = true;
+        return Err(());
       }
       let files_in_dump = files_in_dump.unwrap();
 
@@ -167,79 +456,175 @@ fn main() {
         return Ok(());
       }
 
+      // Only one dump downloads in the background at a time, so finish the last prefetch (most
+      // likely of this dump) before starting on the next one.
+      if should_prefetch {
+        wait_for_prefetch(prefetch.take());
+        let next_dump_id = upcoming_dumps
+          .iter()
+          .skip_while(|dump_id| **dump_id != dump.dump_id)
+          .skip(1)
+          .find(|dump_id| !is_dump_processed(&whiskey, dump_id))
+          .cloned();
+        if let Some(next_dump_id) = next_dump_id {
+          info!("Prefetching dump: {} while dump: {} imports", next_dump_id, dump.dump_id);
+          let prefetch_client = api_client.clone();
+          let prefetch_dump_id = next_dump_id.clone();
+          prefetch = Some((
+            next_dump_id,
+            thread::spawn(move || prefetch_client.download_files_for_dump(prefetch_dump_id)),
+          ));
+        }
+      }
+
       // Set that we're attempting to improt this.
       let _ = whiskey.put(
         format!("dump_processed_{}", dump.dump_id.clone()).as_bytes(),
         b"in_progress",
       );
 
-      // If we have postgres compatability, and are configured for postgres, import that.
-      if cfg!(feature = "postgres_compat") {
-        if settings.get_database_type() == DatabaseType::Psql {
-          info!("Connecting to the DB");
-          let db_client = db_client::DatabaseClient::<PostgresConnectionManager>::new(&settings)
-            .expect("Couldn't setup DB Client");
-          let importer = importer::Importer::<DatabaseClient<PostgresConnectionManager>>::new(
-            api_client.clone(),
-            db_client,
-            dump.dump_id.clone(),
-            settings.get_save_location(),
+      let res = with_configured_adapter(
+        settings,
+        DumpImport {
+          api_client: &api_client,
+          whiskey: &whiskey,
+          settings: settings,
+          dump_id: &dump.dump_id,
+          dump_schema: dump_schema,
+          latest_schema: &latest_schema,
+          is_all_volatile: is_all_volatile,
+        },
+      );
+      match res.and_then(|res| res) {
+        Ok(report) => {
+          log_import_report(&dump.dump_id, &report);
+          let _ = whiskey.put(
+            format!("dump_processed_{}", dump.dump_id).as_bytes(),
+            b"successful",
           );
-          let res = if last_processed_schema.as_str() != latest_schema.version {
-            // If not latest schema. Volatile the table to ensure tables are the latest.
-            importer.process(true)
-          } else {
-            importer.process(settings.get_all_tables_volatile())
-          };
-          if res.is_ok() {
-            let _ = whiskey.put(
-              format!("dump_processed_{}", dump.dump_id).as_bytes(),
-              b"successful",
-            );
-            return Ok(());
-          } else {
-            let _ = whiskey.put(
-              format!("dump_processed_{}", dump.dump_id).as_bytes(),
-              b"failure",
-            );
-            return Err(());
+          if let Err(err) = retention::apply_retention(settings, &whiskey, &all_dump_ids) {
+            error!("Failed to apply the retention policy: {}", err);
           }
+          Ok(())
         }
-      }
-
-      // If we have mysql compatability, and are configured for mysql, import that.
-      if cfg!(feature = "mysql_compat") {
-        if settings.get_database_type() == DatabaseType::Mysql {
-          info!("Connecting to the DB");
-          let db_client = db_client::DatabaseClient::<MysqlConnectionManager>::new(&settings)
-            .expect("Couldn't setup DB Client");
-          let importer = importer::Importer::<DatabaseClient<MysqlConnectionManager>>::new(
-            api_client.clone(),
-            db_client,
-            dump.dump_id.clone(),
-            settings.get_save_location(),
+        Err(err) => {
+          error!("Failed to import dump: {}: {}", dump.dump_id, err);
+          let _ = whiskey.put(
+            format!("dump_processed_{}", dump.dump_id).as_bytes(),
+            b"failure",
           );
-          let res = importer.process(settings.get_all_tables_volatile());
-          if res.is_ok() {
-            let _ = whiskey.put(
-              format!("dump_processed_{}", dump.dump_id).as_bytes(),
-              b"successful",
-            );
-            return Ok(());
-          } else {
-            let _ = whiskey.put(
-              format!("dump_processed_{}", dump.dump_id).as_bytes(),
-              b"failure",
-            );
-            return Err(());
-          }
+          has_errord = true;
+          Err(())
         }
       }
-
-      Err(())
     })
     .collect();
+  wait_for_prefetch(prefetch.take());
+
+  let _ = whiskey.put(
+    "last_version_processed".as_bytes(),
+    latest_schema.version.as_bytes()
+  );
+
+  if has_errord {
+    error!("Failed to import one or more dumps");
+    process::exit(1);
+  }
+
+  info!("Done!");
+}
+
+/// Imports a single dump from the Canvas Data API.
+struct DumpImport<'a> {
+  /// The API Client to use.
+  api_client: &'a CanvasDataApiClient,
+  /// The state store.
+  whiskey: &'a Arc<DB>,
+  /// The settings to import with.
+  settings: &'a Settings,
+  /// The dump to import.
+  dump_id: &'a str,
+  /// The schema the dump is on.
+  dump_schema: SchemaDefinition,
+  /// The latest schema, to migrate the tables to.
+  latest_schema: &'a SchemaDefinition,
+  /// Whether to treat every table as volatile.
+  is_all_volatile: bool,
+}
+
+impl<'a> AdapterImport for DumpImport<'a> {
+  type Output = errors::Result<importer::ImportReport>;
+
+  fn import_with<T: ImportDatabaseAdapter>(self, db_client: T) -> Self::Output {
+    let mut importer = importer::Importer::<T>::new(
+      self.api_client.clone(),
+      db_client,
+      self.dump_id.to_owned(),
+      self.settings.get_save_location(),
+      self.dump_schema,
+    );
+    importer.set_state_store(self.whiskey.clone());
+    importer
+      .process(self.is_all_volatile)
+      .and_then(|report| importer.migrate_to_schema(self.latest_schema).map(|_| report))
+  }
+}
+
+/// Rebuilds every table from the sync file set.
+///
+/// The sync file set is the minimal list of files that make up the current state of every
+/// table, so every table is dropped and reloaded from it in a single pass.
+///
+/// * `settings` - The settings to import with.
+fn import_sync(settings: &Settings) {
+  info!("Setting up API Client...");
+  let api_client = api_client::CanvasDataApiClient::new(settings)
+    .unwrap_or_else(|err| exit_with_error("Failed to setup API Client", err));
+
+  // Connect to the local KV Store.
+  info!("Connecting to RocksDB Store....");
+  let whiskey = Arc::new(DB::open_default(settings.get_rocksdb_location()).expect("Failed to open RocksDB"));
+
+  // List the dumps before the sync file set is fetched, since a dump that finishes after that
+  // isn't part of it, and still needs to be imported later.
+  info!("Listing the dumps in the sync file set...");
+  let synced_dump_ids: Vec<String> = api_client
+    .iter_dumps()
+    .filter(|dump| dump.as_ref().map(|dump| dump.finished).unwrap_or(true))
+    .map(|dump| dump.map(|dump| dump.dump_id))
+    .collect::<errors::Result<Vec<String>>>()
+    .unwrap_or_else(|err| exit_with_error("Failed to list the dumps", err));
 
+  info!("Downloading the sync file set...");
+  let sync_response = api_client
+    .download_sync_files()
+    .unwrap_or_else(|err| exit_with_error("Failed to download the sync file set", err));
+  let latest_schema = api_client
+    .get_latest_schema()
+    .unwrap_or_else(|err| exit_with_error("Failed to fetch latest schema", err));
+  let sync_schema = api_client
+    .get_schema(&sync_response.schema_version)
+    .unwrap_or_else(|err| exit_with_error("Failed to fetch the sync file set's schema", err));
+
+  let res = with_configured_adapter(
+    settings,
+    SyncImport {
+      api_client: &api_client,
+      whiskey: &whiskey,
+      settings: settings,
+      sync_schema: sync_schema,
+      latest_schema: &latest_schema,
+    },
+  );
+  if let Err(err) = res.and_then(|res| res) {
+    exit_with_error("Failed to import the sync file set", err);
+  }
+
+  // Every dump that finished before the sync file set was fetched is part of it, so don't
+  // import them again later.
+  for dump_id in synced_dump_ids {
+    let _ = whiskey.put(format!("dump_processed_{}", dump_id).as_bytes(), b"successful");
+  }
   let _ = whiskey.put(
     "last_version_processed".as_bytes(),
     latest_schema.version.as_bytes()
@@ -247,3 +632,445 @@ fn main() {
 
   info!("Done!");
 }
+
+/// Imports the downloaded sync file set.
+struct SyncImport<'a> {
+  /// The API Client to use.
+  api_client: &'a CanvasDataApiClient,
+  /// The state store.
+  whiskey: &'a Arc<DB>,
+  /// The settings to import with.
+  settings: &'a Settings,
+  /// The schema the sync file set is on.
+  sync_schema: SchemaDefinition,
+  /// The latest schema, to migrate the tables to.
+  latest_schema: &'a SchemaDefinition,
+}
+
+impl<'a> AdapterImport for SyncImport<'a> {
+  type Output = errors::Result<()>;
+
+  fn import_with<T: ImportDatabaseAdapter>(self, db_client: T) -> Self::Output {
+    let mut importer = importer::Importer::<T>::new(
+      self.api_client.clone(),
+      db_client,
+      SYNC_DIRECTORY.to_owned(),
+      self.settings.get_save_location(),
+      self.sync_schema,
+    );
+    importer.set_state_store(self.whiskey.clone());
+    importer.import_downloaded(true).and_then(|_| importer.migrate_to_schema(self.latest_schema))
+  }
+}
+
+/// Rebuilds a single table from its history of files across every dump.
+///
+/// * `settings` - The settings to import with.
+/// * `table_name` - The table to rebuild.
+fn backfill_table(settings: &Settings, table_name: String) {
+  info!("Setting up API Client...");
+  let api_client = api_client::CanvasDataApiClient::new(settings)
+    .unwrap_or_else(|err| exit_with_error("Failed to setup API Client", err));
+  let latest_schema = api_client
+    .get_latest_schema()
+    .unwrap_or_else(|err| exit_with_error("Failed to fetch latest schema", err));
+  if latest_schema.get_table_definition(&table_name).is_none() {
+    error!("Table: {} isn't in the latest schema", table_name);
+    process::exit(1);
+  }
+
+  // Connect to the local KV Store.
+  info!("Connecting to RocksDB Store....");
+  let whiskey = Arc::new(DB::open_default(settings.get_rocksdb_location()).expect("Failed to open RocksDB"));
+
+  let res = with_configured_adapter(
+    settings,
+    TableBackfill {
+      api_client: &api_client,
+      whiskey: &whiskey,
+      settings: settings,
+      latest_schema: latest_schema,
+      table_name: &table_name,
+    },
+  );
+  if let Err(err) = res.and_then(|res| res) {
+    exit_with_error(&format!("Failed to backfill table: {}", table_name), err);
+  }
+
+  info!("Done!");
+}
+
+/// Rebuilds a single table from its history.
+struct TableBackfill<'a> {
+  /// The API Client to use.
+  api_client: &'a CanvasDataApiClient,
+  /// The state store.
+  whiskey: &'a Arc<DB>,
+  /// The settings to import with.
+  settings: &'a Settings,
+  /// The latest schema.
+  latest_schema: SchemaDefinition,
+  /// The table to rebuild.
+  table_name: &'a str,
+}
+
+impl<'a> AdapterImport for TableBackfill<'a> {
+  type Output = errors::Result<()>;
+
+  fn import_with<T: ImportDatabaseAdapter>(self, db_client: T) -> Self::Output {
+    let mut importer = importer::Importer::<T>::new(
+      self.api_client.clone(),
+      db_client,
+      format!("{}/{}", TABLES_DIRECTORY, self.table_name),
+      self.settings.get_save_location(),
+      self.latest_schema,
+    );
+    importer.set_state_store(self.whiskey.clone());
+    importer.backfill_table(self.table_name)
+  }
+}
+
+/// Imports a single dump from a directory of files, without talking to the Canvas Data API.
+///
+/// The directory needs the dump's `.gz` files, the schema the dump is on (as returned by
+/// `/api/schema/{version}`), and the dump's file listing (as returned by
+/// `/api/account/self/file/byDump/{dump_id}`).
+///
+/// * `settings` - The settings to import with.
+/// * `directory` - The directory the dump's files were copied to.
+/// * `schema_path` - The path of the saved schema JSON. Defaults to the recorded
+///                   `schema-{version}.json` in the directory for the dump's schema version.
+/// * `files_path` - The path of the saved file listing JSON.
+/// * `is_replay` - Whether this is replaying the recorded responses of a dump. A replayed dump is
+///                 imported even if it's already been imported, and is handled against the latest
+///                 schema recorded with it, the same as when it was first imported.
+fn import_offline(
+  settings: &Settings,
+  directory: String,
+  schema_path: Option<String>,
+  files_path: String,
+  is_replay: bool,
+) {
+  let files_in_dump: FilesInDumpResponse = read_json_file(&files_path)
+    .unwrap_or_else(|err| exit_with_error(&format!("Failed to read file listing: {}", files_path), err));
+  let schema_path = schema_path.unwrap_or(format!("{}/schema-{}.json", directory, files_in_dump.schema_version));
+  let schema: SchemaDefinition = read_json_file(&schema_path)
+    .unwrap_or_else(|err| exit_with_error(&format!("Failed to read schema: {}", schema_path), err));
+  if files_in_dump.schema_version != schema.version {
+    exit_with_error(
+      &format!("Can't import dump: {}", files_in_dump.dump_id),
+      ErrorKind::SchemaVersionMismatch(files_in_dump.schema_version.clone(), schema.version.clone()).into(),
+    );
+  }
+  if !Path::new(&directory).is_dir() {
+    error!("The offline directory: {} doesn't exist", directory);
+    process::exit(1);
+  }
+
+  // An offline dump is all there is to know about the schema, but a replayed dump has the latest
+  // schema from when it was recorded.
+  let latest_schema_path = format!("{}/schema-latest.json", directory);
+  let latest_schema = if is_replay && Path::new(&latest_schema_path).is_file() {
+    read_json_file(&latest_schema_path)
+      .unwrap_or_else(|err| exit_with_error(&format!("Failed to read schema: {}", latest_schema_path), err))
+  } else {
+    schema.clone()
+  };
+
+  // Connect to the local KV Store.
+  info!("Connecting to RocksDB Store....");
+  let whiskey = Arc::new(DB::open_default(settings.get_rocksdb_location()).expect("Failed to open RocksDB"));
+  let dump_id = files_in_dump.dump_id.clone();
+
+  // Check if we've already processed this dump.
+  if let Ok(Some(processed)) = whiskey.get(format!("dump_processed_{}", dump_id).as_bytes()) {
+    if processed.to_utf8() == Some("successful") && !is_replay {
+      info!("Skipping already processed dump: {}", dump_id);
+      return;
+    }
+  }
+
+  // Check if the dump is a historical refresh.
+  if files_in_dump.is_historical_refresh() && settings.get_should_skip_historical_imports() {
+    info!("Skipping dump: {} since it's a historical refresh", dump_id);
+    let _ = whiskey.put(format!("dump_processed_{}", dump_id).as_bytes(), b"successful");
+    return;
+  }
+
+  // Decide what to do with the dump the same way as if it was imported from the API.
+  let plan = plan_dump_import(settings, &dump_id, &schema.version, &latest_schema, |_| Ok(schema.clone()));
+  let schema = match plan {
+    Ok(DumpPlan::Import(schema)) => schema,
+    Ok(DumpPlan::Skip(state)) => {
+      let _ = whiskey.put(format!("dump_processed_{}", dump_id).as_bytes(), state.as_bytes());
+      return;
+    }
+    Err(err) => exit_with_error(&format!("Can't import dump: {}", dump_id), err),
+  };
+  let is_all_volatile = get_is_all_volatile(settings, &whiskey, &latest_schema);
+
+  // Set that we're attempting to import this.
+  let _ = whiskey.put(format!("dump_processed_{}", dump_id).as_bytes(), b"in_progress");
+
+  let res = with_configured_adapter(
+    settings,
+    OfflineImport {
+      whiskey: &whiskey,
+      directory: &directory,
+      files_in_dump: &files_in_dump,
+      schema: schema,
+      latest_schema: &latest_schema,
+      is_all_volatile: is_all_volatile,
+    },
+  );
+  if let Err(err) = res.and_then(|res| res) {
+    let _ = whiskey.put(format!("dump_processed_{}", dump_id).as_bytes(), b"failure");
+    exit_with_error(&format!("Failed to import dump: {}", dump_id), err);
+  }
+  let _ = whiskey.put(format!("dump_processed_{}", dump_id).as_bytes(), b"successful");
+  // A replayed dump is usually older than what's been imported since, so it doesn't move the last
+  // schema version processed.
+  if !is_replay {
+    let _ = whiskey.put("last_version_processed".as_bytes(), latest_schema.version.as_bytes());
+  }
+
+  info!("Done!");
+}
+
+/// Imports a dump from a directory of files.
+struct OfflineImport<'a> {
+  /// The state store.
+  whiskey: &'a Arc<DB>,
+  /// The directory the dump's files were copied to.
+  directory: &'a str,
+  /// The dump's saved file listing.
+  files_in_dump: &'a FilesInDumpResponse,
+  /// The schema the dump is on.
+  schema: SchemaDefinition,
+  /// The latest schema, to migrate the tables to.
+  latest_schema: &'a SchemaDefinition,
+  /// Whether to treat every table as volatile.
+  is_all_volatile: bool,
+}
+
+impl<'a> AdapterImport for OfflineImport<'a> {
+  type Output = errors::Result<()>;
+
+  fn import_with<T: ImportDatabaseAdapter>(self, db_client: T) -> Self::Output {
+    let mut importer = importer::Importer::<T>::new_offline(
+      db_client,
+      self.files_in_dump.dump_id.clone(),
+      self.directory.to_owned(),
+      self.schema,
+    );
+    importer.set_state_store(self.whiskey.clone());
+    importer
+      .import_offline(&self.files_in_dump.get_files(), self.is_all_volatile)
+      .and_then(|_| importer.migrate_to_schema(self.latest_schema))
+  }
+}
+
+/// Syncs every configured table from Canvas Data 2 (DAP).
+///
+/// Each table's last sync timestamp, and schema version are kept in the state store. A table
+/// that has never been synced, or whose schema version changed, is reloaded from a snapshot.
+/// Otherwise only the changes since the last sync are applied.
+///
+/// * `settings` - The settings to import with.
+fn import_dap(settings: &Settings) {
+  info!("Setting up DAP Client...");
+  let dap_client = DapClient::new(settings).unwrap_or_else(|err| exit_with_error("Failed to setup DAP Client", err));
+
+  // Connect to the local KV Store.
+  info!("Connecting to RocksDB Store....");
+  let whiskey = Arc::new(DB::open_default(settings.get_rocksdb_location()).expect("Failed to open RocksDB"));
+
+  let mut tables = settings.get_dap_tables();
+  if tables.is_empty() {
+    tables = dap_client
+      .list_tables()
+      .unwrap_or_else(|err| exit_with_error("Failed to list DAP tables", err));
+  }
+
+  let res = with_configured_adapter(
+    settings,
+    DapImport {
+      dap_client: &dap_client,
+      whiskey: &whiskey,
+      tables: &tables,
+    },
+  );
+  let has_errord = res.unwrap_or_else(|err| exit_with_error("Failed to sync DAP tables", err));
+  if has_errord {
+    error!("Failed to sync one or more DAP tables");
+    process::exit(1);
+  }
+
+  info!("Done!");
+}
+
+/// Syncs DAP tables, and returns whether any table failed.
+struct DapImport<'a> {
+  /// The DAP Client to use.
+  dap_client: &'a DapClient,
+  /// The state store.
+  whiskey: &'a Arc<DB>,
+  /// The tables to sync.
+  tables: &'a [String],
+}
+
+impl<'a> AdapterImport for DapImport<'a> {
+  type Output = bool;
+
+  fn import_with<T: ImportDatabaseAdapter>(self, db_client: T) -> Self::Output {
+    let mut importer = dap_importer::DapImporter::<T>::new(self.dap_client.clone(), db_client);
+    importer.set_state_store(self.whiskey.clone());
+    let mut has_errord = false;
+
+    for table_name in self.tables.iter() {
+      let schema = match self.dap_client.get_table_schema(table_name) {
+        Ok(schema) => schema,
+        Err(err) => {
+          error!("Failed to get the DAP schema for table: {}: {}", table_name, err);
+          has_errord = true;
+          continue;
+        }
+      };
+
+      // Only sync incrementally if the table was last synced on the same schema version.
+      let last_schema_version = self
+        .whiskey
+        .get(format!("dap_schema_version_{}", table_name).as_bytes())
+        .ok()
+        .and_then(|value| value.and_then(|value| value.to_utf8().map(|value| value.to_owned())));
+      let mut since = self
+        .whiskey
+        .get(format!("dap_last_synced_{}", table_name).as_bytes())
+        .ok()
+        .and_then(|value| value.and_then(|value| value.to_utf8().map(|value| value.to_owned())));
+      if last_schema_version != Some(schema.version.to_string()) {
+        since = None;
+      }
+
+      match importer.sync_table(table_name, &schema, since.as_ref().map(|since| since.as_str())) {
+        Ok(result) => {
+          let _ = self.whiskey.put(
+            format!("dap_last_synced_{}", table_name).as_bytes(),
+            result.timestamp.as_bytes(),
+          );
+          let _ = self.whiskey.put(
+            format!("dap_schema_version_{}", table_name).as_bytes(),
+            schema.version.to_string().as_bytes(),
+          );
+          info!("Synced DAP table: {} up to: {}", table_name, result.timestamp);
+        }
+        Err(err) => {
+          error!("Failed to sync DAP table: {}: {}", table_name, err);
+          has_errord = true;
+        }
+      }
+    }
+
+    has_errord
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::BTreeMap;
+
+  /// Builds a schema without any tables, which is all deciding how to import a dump looks at.
+  ///
+  /// * `version` - The version of the schema.
+  fn empty_schema(version: &str) -> SchemaDefinition {
+    SchemaDefinition {
+      version: version.to_owned(),
+      schema: BTreeMap::new(),
+    }
+  }
+
+  /// Decides how to import a dump on schema version 1.0.0, when the latest is 2.0.0.
+  ///
+  /// * `toml` - The settings to decide with, over the defaults.
+  fn plan_out_of_date_dump(toml: &str) -> errors::Result<DumpPlan> {
+    plan_dump_import(
+      &Settings::from_toml(toml),
+      "dump",
+      "1.0.0",
+      &empty_schema("2.0.0"),
+      |version| Ok(empty_schema(version)),
+    )
+  }
+
+  #[test]
+  fn plan_dump_import_imports_dumps_on_the_latest_schema() {
+    let plan = plan_dump_import(
+      &Settings::from_toml("out_of_date_dumps = \"refuse\"\n"),
+      "dump",
+      "2.0.0",
+      &empty_schema("2.0.0"),
+      |_| panic!("The latest schema doesn't need fetching"),
+    );
+    match plan {
+      Ok(DumpPlan::Import(schema)) => assert_eq!(schema.version, "2.0.0"),
+      _ => panic!("Expected the dump to be imported"),
+    }
+  }
+
+  #[test]
+  fn plan_dump_import_migrates_by_default() {
+    for toml in vec!["", "out_of_date_dumps = \"migrate\"\n", "out_of_date_dumps = \"Migrate\"\n"] {
+      match plan_out_of_date_dump(toml) {
+        Ok(DumpPlan::Import(schema)) => assert_eq!(schema.version, "1.0.0"),
+        _ => panic!("Expected the dump to be imported with its own schema for: {:?}", toml),
+      }
+    }
+  }
+
+  #[test]
+  fn plan_dump_import_refuses_out_of_date_dumps() {
+    match plan_out_of_date_dump("out_of_date_dumps = \"refuse\"\n") {
+      Ok(DumpPlan::Skip(state)) => assert_eq!(state, "refused"),
+      _ => panic!("Expected the dump to be refused"),
+    }
+  }
+
+  #[test]
+  fn plan_dump_import_skips_out_of_date_dumps() {
+    let plan = plan_dump_import(
+      &Settings::from_toml("out_of_date_dumps = \"skip\"\n"),
+      "dump",
+      "1.0.0",
+      &empty_schema("2.0.0"),
+      |_| panic!("A skipped dump's schema doesn't need fetching"),
+    );
+    match plan {
+      Ok(DumpPlan::Skip(state)) => assert_eq!(state, "out-of-date"),
+      _ => panic!("Expected the dump to be skipped"),
+    }
+  }
+
+  #[test]
+  fn plan_dump_import_rejects_unknown_strategies() {
+    match plan_out_of_date_dump("out_of_date_dumps = \"ignore\"\n") {
+      Err(errors::Error(ErrorKind::InvalidSetting(name, value), _)) => {
+        assert_eq!(name, "out_of_date_dumps");
+        assert_eq!(value, "ignore");
+      }
+      _ => panic!("Expected the strategy to be rejected"),
+    }
+  }
+
+  #[test]
+  fn plan_dump_import_fails_when_the_dump_schema_cant_be_fetched() {
+    let plan = plan_dump_import(
+      &Settings::from_toml(""),
+      "dump",
+      "1.0.0",
+      &empty_schema("2.0.0"),
+      |_| Err(ErrorKind::ImportErr.into()),
+    );
+    assert!(plan.is_err());
+  }
+}
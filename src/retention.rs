@@ -0,0 +1,375 @@
+//! Removes the files of dumps that have already been imported, so the save location doesn't
+//! grow forever.
+
+use api_client::{DOWNLOAD_CACHE_DIRECTORY, VERIFIED_FILE_SUFFIX};
+use errors::*;
+use glob::glob;
+use rocksdb::DB;
+use settings::Settings;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// Applies the retention policy to the downloaded dumps.
+///
+/// Only dumps marked `successful` in the state store are ever removed. If deleting after import
+/// is turned on every one of them is removed, otherwise everything but the last N of them is.
+/// Dumps without a directory, like skipped historical refreshes, don't count towards the last N.
+/// Dumps are archived instead of deleted when there's an archive location. Files in the download
+/// cache that no dump uses anymore are removed afterwards, except for the files of the latest
+/// successful dump, which the next dump most likely shares.
+///
+/// * `settings` - The settings with the retention policy.
+/// * `whiskey` - The state store.
+/// * `dump_ids` - Every Dump ID, oldest first.
+pub fn apply_retention(settings: &Settings, whiskey: &DB, dump_ids: &[String]) -> Result<()> {
+  let keep_last = if settings.get_retention_delete_after_import() {
+    0
+  } else {
+    match settings.get_retention_keep_last_successful() {
+      Some(keep_last) => keep_last,
+      None => return Ok(()),
+    }
+  };
+
+  let save_location = settings.get_save_location();
+  let successful_dump_ids: Vec<&String> = dump_ids
+    .iter()
+    .filter(|dump_id| is_dump_successful(whiskey, dump_id) && Path::new(&save_location).join(dump_id).exists())
+    .collect();
+  let remove_count = successful_dump_ids.len().saturating_sub(keep_last);
+  let mut has_removed = false;
+
+  // The latest dump may be about to be removed, so find out what it has before it is.
+  let latest_dump_files = match successful_dump_ids.last() {
+    Some(dump_id) => try!(get_dump_file_names(&Path::new(&save_location).join(dump_id))),
+    None => BTreeSet::new(),
+  };
+
+  for dump_id in successful_dump_ids.into_iter().take(remove_count) {
+    let dump_directory = Path::new(&save_location).join(dump_id);
+    has_removed = true;
+
+    match settings.get_retention_archive_location() {
+      Some(archive_location) => {
+        let archive_directory = Path::new(&archive_location).join(dump_id);
+        info!("Archiving dump: {} to: {:?}", dump_id, archive_directory);
+        try!(move_directory(&dump_directory, &archive_directory));
+      }
+      None => {
+        info!("Removing dump: {} from: {:?}", dump_id, dump_directory);
+        try!(fs::remove_dir_all(&dump_directory));
+      }
+    }
+  }
+
+  if has_removed && settings.get_should_cache_downloads() {
+    try!(prune_download_cache(&save_location, latest_dump_files));
+  }
+
+  Ok(())
+}
+
+/// Gets the names of the files downloaded for a dump.
+///
+/// * `dump_directory` - The dump's directory under the save location.
+fn get_dump_file_names(dump_directory: &Path) -> Result<BTreeSet<String>> {
+  let mut file_names = BTreeSet::new();
+  if !dump_directory.exists() {
+    return Ok(file_names);
+  }
+  for entry in try!(glob(&format!("{}/*.gz", dump_directory.to_string_lossy()))) {
+    if let Ok(path) = entry {
+      if let Some(file_name) = path.file_name() {
+        file_names.insert(file_name.to_string_lossy().into_owned());
+      }
+    }
+  }
+  Ok(file_names)
+}
+
+/// Removes the files in the download cache that aren't used anywhere else under the save location.
+///
+/// * `save_location` - The save location the download cache is under.
+/// * `keep` - The names of files to keep in the download cache, even if they aren't used.
+fn prune_download_cache(save_location: &str, keep: BTreeSet<String>) -> Result<()> {
+  let cache_directory = Path::new(save_location).join(DOWNLOAD_CACHE_DIRECTORY);
+  if !cache_directory.exists() {
+    return Ok(());
+  }
+
+  let mut in_use = keep;
+  for entry in try!(glob(&format!("{}/**/*.gz", save_location))) {
+    if let Ok(path) = entry {
+      if path.starts_with(&cache_directory) {
+        continue;
+      }
+      if let Some(file_name) = path.file_name() {
+        in_use.insert(file_name.to_string_lossy().into_owned());
+      }
+    }
+  }
+
+  for entry in try!(fs::read_dir(&cache_directory)) {
+    let entry = try!(entry);
+    // A file's verified record goes along with the file.
+    let file_name = entry.file_name().to_string_lossy().into_owned();
+    if !in_use.contains(file_name.trim_right_matches(VERIFIED_FILE_SUFFIX)) {
+      debug!("Removing unused file from the download cache: {:?}", entry.path());
+      try!(fs::remove_file(entry.path()));
+    }
+  }
+
+  Ok(())
+}
+
+/// Checks the state store for whether a dump was imported successfully.
+///
+/// * `whiskey` - The state store.
+/// * `dump_id` - The Dump ID to check.
+fn is_dump_successful(whiskey: &DB, dump_id: &str) -> bool {
+  match whiskey.get(format!("dump_processed_{}", dump_id).as_bytes()) {
+    Ok(Some(processed)) => processed.to_utf8() == Some("successful"),
+    _ => false,
+  }
+}
+
+/// Moves a directory of files, copying them if the destination is on another filesystem.
+///
+/// An existing destination is never replaced. Copies are made into a temporary directory next to
+/// the destination, and only renamed into place once every file is copied, so a failed copy never
+/// leaves a half-written directory behind, or loses the source.
+///
+/// * `from` - The directory to move.
+/// * `to` - Where to move the directory to.
+fn move_directory(from: &Path, to: &Path) -> Result<()> {
+  if to.exists() {
+    return Err(ErrorKind::ArchiveExists(to.to_string_lossy().into_owned()).into());
+  }
+  if let Some(parent) = to.parent() {
+    try!(fs::create_dir_all(parent));
+  }
+  if fs::rename(from, to).is_ok() {
+    return Ok(());
+  }
+
+  // Renames can't cross filesystems, so copy the files over instead.
+  let mut copy_name = to.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+  copy_name.push(".part");
+  let copy_directory = to.with_file_name(copy_name);
+  if copy_directory.exists() {
+    // Left over from a copy that failed part way through.
+    try!(fs::remove_dir_all(&copy_directory));
+  }
+  if let Err(err) = copy_files(from, &copy_directory) {
+    let _ = fs::remove_dir_all(&copy_directory);
+    return Err(err);
+  }
+  try!(fs::rename(&copy_directory, to));
+  try!(fs::remove_dir_all(from));
+  Ok(())
+}
+
+/// Copies the files directly in a directory into a new directory.
+///
+/// * `from` - The directory to copy the files of.
+/// * `to` - The directory to create, and copy the files into.
+fn copy_files(from: &Path, to: &Path) -> Result<()> {
+  try!(fs::create_dir_all(to));
+  for entry in try!(fs::read_dir(from)) {
+    let entry = try!(entry);
+    if try!(entry.file_type()).is_file() {
+      try!(fs::copy(entry.path(), to.join(entry.file_name())));
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::path::PathBuf;
+  use std::process;
+
+  /// Creates an empty save location for a test, along with a state store outside of it.
+  ///
+  /// * `name` - The name of the test.
+  fn test_directory(name: &str) -> (PathBuf, DB) {
+    let directory = env::temp_dir().join(format!("cdl-retention-test-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(directory.join("save")).expect("Failed to create the save location");
+    let whiskey = DB::open_default(directory.join("rocksdb")).expect("Failed to open RocksDB");
+    (directory, whiskey)
+  }
+
+  /// Creates settings that save to a test's save location.
+  ///
+  /// * `directory` - The test's directory.
+  /// * `cache_downloads` - Whether the download cache is turned on.
+  /// * `retention` - The settings of the retention section.
+  fn test_settings(directory: &Path, cache_downloads: bool, retention: &str) -> Settings {
+    Settings::from_toml(&format!(
+      "save_location = {:?}\ncache_downloads = {}\n[retention]\n{}",
+      directory.join("save").to_string_lossy(),
+      cache_downloads,
+      retention
+    ))
+  }
+
+  /// Records how a dump was processed, and writes its files, if it has any.
+  ///
+  /// * `directory` - The test's directory.
+  /// * `whiskey` - The state store.
+  /// * `dump_id` - The dump.
+  /// * `state` - How the dump was processed.
+  /// * `files` - The names of the dump's files. A dump without files has no directory.
+  fn add_dump(directory: &Path, whiskey: &DB, dump_id: &str, state: &str, files: &[&str]) {
+    whiskey
+      .put(format!("dump_processed_{}", dump_id).as_bytes(), state.as_bytes())
+      .expect("Failed to record the dump");
+    if files.is_empty() {
+      return;
+    }
+    let dump_directory = directory.join("save").join(dump_id);
+    fs::create_dir_all(&dump_directory).expect("Failed to create the dump directory");
+    for file in files.iter() {
+      fs::write(dump_directory.join(file), file).expect("Failed to write a dump file");
+    }
+  }
+
+  /// Lists the names of the entries in a directory, sorted.
+  ///
+  /// * `directory` - The directory to list.
+  fn list_directory(directory: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(directory)
+      .expect("Failed to list the directory")
+      .map(|entry| entry.expect("Failed to read an entry").file_name().to_string_lossy().into_owned())
+      .collect();
+    names.sort();
+    names
+  }
+
+  /// Gets the Dump IDs to apply retention to.
+  ///
+  /// * `dump_ids` - The Dump IDs, oldest first.
+  fn ids(dump_ids: &[&str]) -> Vec<String> {
+    dump_ids.iter().map(|dump_id| dump_id.to_string()).collect()
+  }
+
+  /// Removes a test's directory, once its state store is closed.
+  ///
+  /// * `directory` - The test's directory.
+  /// * `whiskey` - The test's state store.
+  fn clean_up(directory: PathBuf, whiskey: DB) {
+    drop(whiskey);
+    let _ = fs::remove_dir_all(&directory);
+  }
+
+  #[test]
+  fn apply_retention_does_nothing_without_a_policy() {
+    let (directory, whiskey) = test_directory("no-policy");
+    add_dump(&directory, &whiskey, "d1", "successful", &["a.gz"]);
+    let settings = test_settings(&directory, false, "");
+
+    apply_retention(&settings, &whiskey, &ids(&["d1"])).expect("Failed to apply retention");
+    assert_eq!(list_directory(&directory.join("save")), vec!["d1"]);
+    clean_up(directory, whiskey);
+  }
+
+  #[test]
+  fn apply_retention_keeps_the_last_successful_dumps() {
+    let (directory, whiskey) = test_directory("keep-last");
+    for dump_id in ["d1", "d2", "d3", "d4"].iter() {
+      add_dump(&directory, &whiskey, dump_id, "successful", &["a.gz"]);
+    }
+    let settings = test_settings(&directory, false, "keep_last_successful = 2\n");
+
+    apply_retention(&settings, &whiskey, &ids(&["d1", "d2", "d3", "d4"])).expect("Failed to apply retention");
+    assert_eq!(list_directory(&directory.join("save")), vec!["d3", "d4"]);
+    clean_up(directory, whiskey);
+  }
+
+  #[test]
+  fn apply_retention_only_counts_dumps_with_a_directory() {
+    let (directory, whiskey) = test_directory("keep-last-directories");
+    add_dump(&directory, &whiskey, "d1", "successful", &["a.gz"]);
+    add_dump(&directory, &whiskey, "d2", "successful", &["a.gz"]);
+    // Like a skipped historical refresh, or a dump marked by a sync.
+    add_dump(&directory, &whiskey, "d3", "successful", &[]);
+    let settings = test_settings(&directory, false, "keep_last_successful = 2\n");
+
+    apply_retention(&settings, &whiskey, &ids(&["d1", "d2", "d3"])).expect("Failed to apply retention");
+    assert_eq!(list_directory(&directory.join("save")), vec!["d1", "d2"]);
+    clean_up(directory, whiskey);
+  }
+
+  #[test]
+  fn apply_retention_only_removes_successful_dumps() {
+    let (directory, whiskey) = test_directory("only-successful");
+    add_dump(&directory, &whiskey, "d1", "failure", &["a.gz"]);
+    add_dump(&directory, &whiskey, "d2", "successful", &["a.gz"]);
+    add_dump(&directory, &whiskey, "d3", "in_progress", &["a.gz"]);
+    add_dump(&directory, &whiskey, "d4", "successful", &["a.gz"]);
+    let settings = test_settings(&directory, false, "keep_last_successful = 1\n");
+
+    apply_retention(&settings, &whiskey, &ids(&["d1", "d2", "d3", "d4"])).expect("Failed to apply retention");
+    assert_eq!(list_directory(&directory.join("save")), vec!["d1", "d3", "d4"]);
+    clean_up(directory, whiskey);
+  }
+
+  #[test]
+  fn apply_retention_deletes_every_successful_dump_after_import() {
+    let (directory, whiskey) = test_directory("delete-after-import");
+    add_dump(&directory, &whiskey, "d1", "successful", &["a.gz"]);
+    add_dump(&directory, &whiskey, "d2", "failure", &["a.gz"]);
+    add_dump(&directory, &whiskey, "d3", "successful", &["a.gz"]);
+    let settings = test_settings(&directory, false, "keep_last_successful = 5\ndelete_after_import = true\n");
+
+    apply_retention(&settings, &whiskey, &ids(&["d1", "d2", "d3"])).expect("Failed to apply retention");
+    assert_eq!(list_directory(&directory.join("save")), vec!["d2"]);
+    clean_up(directory, whiskey);
+  }
+
+  #[test]
+  fn apply_retention_archives_dumps_without_replacing_archives() {
+    let (directory, whiskey) = test_directory("archive");
+    add_dump(&directory, &whiskey, "d1", "successful", &["a.gz"]);
+    add_dump(&directory, &whiskey, "d2", "successful", &["b.gz"]);
+    let archive = directory.join("archive");
+    let settings = test_settings(
+      &directory,
+      false,
+      &format!("delete_after_import = true\narchive_location = {:?}\n", archive.to_string_lossy()),
+    );
+
+    // An existing archive of a dump stays as it is, along with the dump.
+    fs::create_dir_all(archive.join("d2")).expect("Failed to create the existing archive");
+    fs::write(archive.join("d2").join("old.gz"), "old").expect("Failed to write the existing archive");
+    assert!(apply_retention(&settings, &whiskey, &ids(&["d1", "d2"])).is_err());
+    assert_eq!(list_directory(&archive.join("d1")), vec!["a.gz"]);
+    assert_eq!(list_directory(&archive.join("d2")), vec!["old.gz"]);
+    assert_eq!(list_directory(&directory.join("save")), vec!["d2"]);
+    clean_up(directory, whiskey);
+  }
+
+  #[test]
+  fn apply_retention_prunes_the_download_cache() {
+    let (directory, whiskey) = test_directory("prune-cache");
+    add_dump(&directory, &whiskey, "d1", "successful", &["x.gz"]);
+    add_dump(&directory, &whiskey, "d2", "successful", &["y.gz"]);
+    add_dump(&directory, &whiskey, "d3", "failure", &["w.gz"]);
+    let cache_directory = directory.join("save").join(DOWNLOAD_CACHE_DIRECTORY);
+    fs::create_dir_all(&cache_directory).expect("Failed to create the download cache");
+    for file in ["w.gz", "x.gz", "x.gz.verified", "y.gz", "y.gz.verified", "z.gz"].iter() {
+      fs::write(cache_directory.join(file), file).expect("Failed to write a cached file");
+    }
+    let settings = test_settings(&directory, true, "delete_after_import = true\n");
+
+    apply_retention(&settings, &whiskey, &ids(&["d1", "d2", "d3"])).expect("Failed to apply retention");
+    assert_eq!(list_directory(&directory.join("save")), vec!["cache", "d3"]);
+    // The failed dump still uses w.gz, and the latest successful dump's y.gz is kept for the next one.
+    assert_eq!(list_directory(&cache_directory), vec!["w.gz", "y.gz", "y.gz.verified"]);
+    clean_up(directory, whiskey);
+  }
+}
@@ -0,0 +1,209 @@
+//! Compares two versions of the Canvas Data Schema, so dumps on an older schema version
+//! can be reported on, or migrated forward.
+
+use api_client::{ColumnDefinition, SchemaDefinition, TableDefinition};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A single difference between two schema versions.
+#[derive(Clone, Debug)]
+pub enum SchemaChange {
+  /// A table only exists in the newer schema.
+  TableAdded(String),
+  /// A table only exists in the older schema.
+  TableRemoved(String),
+  /// A column only exists in the newer schema. (table name, new column).
+  ColumnAdded(String, ColumnDefinition),
+  /// A column only exists in the older schema. (table name, column name).
+  ColumnRemoved(String, String),
+  /// A column's type is different in the newer schema. (table name, old type, new column).
+  ColumnTypeChanged(String, String, ColumnDefinition),
+}
+
+impl fmt::Display for SchemaChange {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      SchemaChange::TableAdded(ref table) => write!(f, "table {} was added", table),
+      SchemaChange::TableRemoved(ref table) => write!(f, "table {} was removed", table),
+      SchemaChange::ColumnAdded(ref table, ref column) => {
+        write!(f, "column {}.{} ({}) was added", table, column.name, column.db_type)
+      }
+      SchemaChange::ColumnRemoved(ref table, ref column) => write!(f, "column {}.{} was removed", table, column),
+      SchemaChange::ColumnTypeChanged(ref table, ref old_type, ref column) => {
+        write!(
+          f,
+          "column {}.{} changed type from {} to {}",
+          table,
+          column.name,
+          old_type,
+          column.db_type
+        )
+      }
+    }
+  }
+}
+
+/// Keys the tables of a schema by their lowercased table name.
+///
+/// * `schema` - The schema to key.
+fn tables_by_name(schema: &SchemaDefinition) -> BTreeMap<String, &TableDefinition> {
+  schema
+    .schema
+    .values()
+    .map(|table_def| (table_def.table_name.to_lowercase(), table_def))
+    .collect()
+}
+
+/// Lists every change needed to go from one schema version to another.
+///
+/// * `from` - The older schema version.
+/// * `to` - The newer schema version.
+pub fn diff_schemas(from: &SchemaDefinition, to: &SchemaDefinition) -> Vec<SchemaChange> {
+  let mut changes = Vec::new();
+  let from_tables = tables_by_name(from);
+  let to_tables = tables_by_name(to);
+
+  for (table_name, to_table) in to_tables.iter() {
+    let from_table = match from_tables.get(table_name) {
+      Some(from_table) => from_table,
+      None => {
+        changes.push(SchemaChange::TableAdded(table_name.clone()));
+        continue;
+      }
+    };
+
+    for to_column in to_table.columns.iter() {
+      match from_table.columns.iter().find(|column| column.name == to_column.name) {
+        None => changes.push(SchemaChange::ColumnAdded(table_name.clone(), to_column.clone())),
+        Some(from_column) => {
+          if from_column.db_type != to_column.db_type {
+            changes.push(SchemaChange::ColumnTypeChanged(
+              table_name.clone(),
+              from_column.db_type.clone(),
+              to_column.clone(),
+            ));
+          }
+        }
+      }
+    }
+    for from_column in from_table.columns.iter() {
+      if !to_table.columns.iter().any(|column| column.name == from_column.name) {
+        changes.push(SchemaChange::ColumnRemoved(table_name.clone(), from_column.name.clone()));
+      }
+    }
+  }
+
+  for table_name in from_tables.keys() {
+    if !to_tables.contains_key(table_name) {
+      changes.push(SchemaChange::TableRemoved(table_name.clone()));
+    }
+  }
+
+  changes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Builds a column definition.
+  ///
+  /// * `name` - The name of the column.
+  /// * `db_type` - The type of the column.
+  fn column(name: &str, db_type: &str) -> ColumnDefinition {
+    ColumnDefinition {
+      db_type: db_type.to_owned(),
+      description: None,
+      name: name.to_owned(),
+      length: None,
+      dimension: None,
+    }
+  }
+
+  /// Builds a schema from its tables, and their columns.
+  ///
+  /// * `version` - The version of the schema.
+  /// * `tables` - The table names, and their columns.
+  fn schema(version: &str, tables: Vec<(&str, Vec<ColumnDefinition>)>) -> SchemaDefinition {
+    SchemaDefinition {
+      version: version.to_owned(),
+      schema: tables
+        .into_iter()
+        .map(|(table_name, columns)| {
+          (
+            table_name.to_owned(),
+            TableDefinition {
+              dw_type: "dimension".to_owned(),
+              description: None,
+              hints: BTreeMap::new(),
+              incremental: false,
+              table_name: table_name.to_owned(),
+              columns: columns,
+            },
+          )
+        })
+        .collect(),
+    }
+  }
+
+  /// Describes every change between two schemas.
+  ///
+  /// * `from` - The older schema version.
+  /// * `to` - The newer schema version.
+  fn describe_diff(from: &SchemaDefinition, to: &SchemaDefinition) -> Vec<String> {
+    diff_schemas(from, to).iter().map(|change| change.to_string()).collect()
+  }
+
+  #[test]
+  fn diff_schemas_finds_nothing_between_the_same_schemas() {
+    let from = schema("1.0.0", vec![("user_dim", vec![column("id", "bigint")])]);
+    let to = schema("1.1.0", vec![("user_dim", vec![column("id", "bigint")])]);
+    assert!(diff_schemas(&from, &to).is_empty());
+  }
+
+  #[test]
+  fn diff_schemas_finds_added_and_removed_tables() {
+    let from = schema("1.0.0", vec![("old_dim", vec![column("id", "bigint")])]);
+    let to = schema("1.1.0", vec![("new_dim", vec![column("id", "bigint")])]);
+    assert_eq!(
+      describe_diff(&from, &to),
+      vec!["table new_dim was added".to_owned(), "table old_dim was removed".to_owned()]
+    );
+  }
+
+  #[test]
+  fn diff_schemas_finds_added_removed_and_changed_columns() {
+    let from = schema(
+      "1.0.0",
+      vec![("user_dim", vec![column("id", "bigint"), column("name", "varchar"), column("old", "int")])],
+    );
+    let to = schema(
+      "1.1.0",
+      vec![("user_dim", vec![column("id", "bigint"), column("name", "text"), column("new", "boolean")])],
+    );
+    assert_eq!(
+      describe_diff(&from, &to),
+      vec![
+        "column user_dim.name changed type from varchar to text".to_owned(),
+        "column user_dim.new (boolean) was added".to_owned(),
+        "column user_dim.old was removed".to_owned(),
+      ]
+    );
+
+    let changes = diff_schemas(&from, &to);
+    match changes[1] {
+      SchemaChange::ColumnAdded(ref table_name, ref column) => {
+        assert_eq!(table_name, "user_dim");
+        assert_eq!(column.db_type, "boolean");
+      }
+      ref other => panic!("Expected an added column, got: {}", other),
+    }
+  }
+
+  #[test]
+  fn diff_schemas_matches_tables_regardless_of_case() {
+    let from = schema("1.0.0", vec![("User_Dim", vec![column("id", "bigint")])]);
+    let to = schema("1.1.0", vec![("user_dim", vec![column("id", "bigint"), column("name", "varchar")])]);
+    assert_eq!(describe_diff(&from, &to), vec!["column user_dim.name (varchar) was added".to_owned()]);
+  }
+}
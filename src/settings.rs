@@ -2,6 +2,10 @@
 //! configuration values from the environment, or one of several files.
 
 use config::{Config, File, Environment};
+#[cfg(test)]
+use config::FileFormat;
+use errors::*;
+use std::cmp;
 
 /// An Enum of all possible database types.
 ///
@@ -14,6 +18,17 @@ pub enum DatabaseType {
   Mysql,
 }
 
+/// An Enum of all the ways to handle a dump that isn't on the latest schema version.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum OutOfDateStrategy {
+  /// Import the dump with its own schema, and then migrate its tables to the latest schema.
+  Migrate,
+  /// Report how the dump's schema differs from the latest schema, and don't import it.
+  Refuse,
+  /// Skip the dump without importing it.
+  Skip,
+}
+
 /// The Database Configuration object.
 ///
 /// Handles all database configuration values, which in this case is just the connection URL.
@@ -23,6 +38,10 @@ struct Database {
   pub url: String,
   /// The Type of The Database.
   pub db_type: String,
+  /// The most rows to insert with a single statement.
+  pub batch_size: Option<usize>,
+  /// The most tables to import at once.
+  pub max_concurrent_tables: Option<usize>,
 }
 
 /// The Canvas Data API Auth Configuration object.
@@ -37,6 +56,74 @@ struct Canvasdataauth {
   pub api_secret: String,
 }
 
+/// The HTTP Configuration object.
+///
+/// Handles how the loader talks over HTTP, such as how many times to retry a failed request.
+#[derive(Debug, Deserialize)]
+struct Http {
+  /// The maximum number of times to retry a failed request.
+  pub max_retries: Option<u32>,
+  /// The delay before the first retry in milliseconds, doubled on each retry.
+  pub retry_base_delay_ms: Option<u64>,
+  /// The maximum delay between retries in milliseconds.
+  pub retry_max_delay_ms: Option<u64>,
+  /// The timeout for connecting to a server in seconds.
+  pub connect_timeout_secs: Option<u64>,
+  /// The timeout for each read, or write on a connection in seconds.
+  pub read_timeout_secs: Option<u64>,
+  /// The URL of the proxy to send all requests through.
+  pub proxy_url: Option<String>,
+  /// The username to authenticate to the proxy with.
+  pub proxy_username: Option<String>,
+  /// The password to authenticate to the proxy with.
+  pub proxy_password: Option<String>,
+  /// Paths to PEM files of extra CA certificates to trust.
+  pub ca_certificates: Option<Vec<String>>,
+  /// The maximum number of files to download at once.
+  pub max_concurrent_downloads: Option<usize>,
+  /// The maximum number of bytes per second to download, across every download.
+  pub max_download_bytes_per_second: Option<u64>,
+  /// The maximum number of downloaded tables waiting to be imported.
+  pub max_pending_tables: Option<usize>,
+}
+
+/// The Canvas Data 2 (DAP) Configuration object.
+///
+/// Handles the OAuth client credentials for the DAP API, and which tables to sync from it.
+#[derive(Debug, Deserialize)]
+struct Dap {
+  /// The OAuth Client ID for the DAP API.
+  pub client_id: String,
+  /// The OAuth Client Secret for the DAP API.
+  pub client_secret: String,
+  /// The Base URL (scheme, host, and port) of the DAP API.
+  pub api_url: Option<String>,
+  /// The namespace of the tables to sync.
+  pub namespace: Option<String>,
+  /// The tables to sync, every table in the namespace if not set.
+  pub tables: Option<Vec<String>>,
+  /// How long to wait between checks on a query job in seconds.
+  pub poll_interval_secs: Option<u64>,
+}
+
+/// The Retention Configuration object.
+///
+/// Handles how long the files of dumps that were imported successfully are kept around.
+#[derive(Debug, Deserialize)]
+struct Retention {
+  /// The number of the most recent successfully imported dumps to keep.
+  pub keep_last_successful: Option<usize>,
+  /// Whether to remove every dump as soon as it's imported successfully.
+  pub delete_after_import: Option<bool>,
+  /// The directory to move removed dumps to, instead of deleting them.
+  pub archive_location: Option<String>,
+}
+
+/// The default base URL for the Canvas Data API.
+const DEFAULT_API_URL: &'static str = "https://portal.inshosteddata.com";
+/// The default base URL for the DAP API.
+const DEFAULT_DAP_API_URL: &'static str = "https://api-gateway.instructure.com";
+
 /// The Global Settings object for all configuration values.
 #[derive(Debug, Deserialize)]
 pub struct Settings {
@@ -44,6 +131,14 @@ pub struct Settings {
   database: Database,
   /// The Canvas Data API Auth Configuration Object.
   canvasdataauth: Canvasdataauth,
+  /// The HTTP Configuration Object.
+  http: Option<Http>,
+  /// The Canvas Data 2 (DAP) Configuration Object.
+  dap: Option<Dap>,
+  /// The Retention Configuration Object.
+  retention: Option<Retention>,
+  /// The Base URL (scheme, host, and port) of the Canvas Data API.
+  api_url: Option<String>,
   /// The place to save files.
   save_location: String,
   /// The place to store the Rocks DB Database.
@@ -54,6 +149,16 @@ pub struct Settings {
   only_load_final: Option<bool>,
   /// Treats all tables as volatile.
   all_tables_volatile: Option<bool>,
+  /// Caches schema definitions on disk under the save location.
+  cache_schemas_on_disk: Option<bool>,
+  /// Saves the raw API responses used for each dump into the dump's directory.
+  record_api_responses: Option<bool>,
+  /// Reuses files already downloaded under another dump, through a cache under the save location.
+  cache_downloads: Option<bool>,
+  /// How to handle dumps that aren't on the latest schema version.
+  out_of_date_dumps: Option<String>,
+  /// Downloads the next dump in the background while the current one imports.
+  prefetch_next_dump: Option<bool>,
 }
 
 impl Settings {
@@ -79,6 +184,24 @@ impl Settings {
     )
   }
 
+  /// Creates a settings object from the default configuration file, with some configuration on top.
+  ///
+  /// * `toml` - The configuration to use over the defaults.
+  #[cfg(test)]
+  pub fn from_toml(toml: &str) -> Self {
+    let mut base_configuration = Config::new();
+    base_configuration
+      .merge(File::with_name("config/default"))
+      .expect("Could not find default configuration file");
+    base_configuration
+      .merge(File::from_str(toml, FileFormat::Toml))
+      .expect("Failed to parse the test configuration");
+
+    base_configuration.try_into().expect(
+      "Failed to create test configuration",
+    )
+  }
+
   /// Gets the save location provided by the settings.
   pub fn get_save_location(&self) -> String {
     self.save_location.clone()
@@ -104,11 +227,59 @@ impl Settings {
     self.all_tables_volatile.unwrap_or(false)
   }
 
+  /// Gets the notion of whether or not to cache schema definitions on disk.
+  pub fn get_should_cache_schemas_on_disk(&self) -> bool {
+    self.cache_schemas_on_disk.unwrap_or(false)
+  }
+
+  /// Gets the notion of whether or not to reuse files already downloaded under another dump.
+  pub fn get_should_cache_downloads(&self) -> bool {
+    self.cache_downloads.unwrap_or(false)
+  }
+
+  /// Gets the notion of whether or not to save the raw API responses used for each dump.
+  pub fn get_should_record_api_responses(&self) -> bool {
+    self.record_api_responses.unwrap_or(false)
+  }
+
+  /// Gets the notion of whether or not to download the next dump while the current one imports.
+  pub fn get_should_prefetch_next_dump(&self) -> bool {
+    self.prefetch_next_dump.unwrap_or(false)
+  }
+
+  /// Gets how to handle dumps that aren't on the latest schema version.
+  ///
+  /// A value that isn't one of the strategies is an error, instead of silently migrating.
+  pub fn get_out_of_date_strategy(&self) -> Result<OutOfDateStrategy> {
+    let out_of_date_dumps = self.out_of_date_dumps.clone().unwrap_or("migrate".to_owned());
+    match out_of_date_dumps.to_lowercase().as_str() {
+      "migrate" => Ok(OutOfDateStrategy::Migrate),
+      "refuse" => Ok(OutOfDateStrategy::Refuse),
+      "skip" => Ok(OutOfDateStrategy::Skip),
+      _ => Err(ErrorKind::InvalidSetting("out_of_date_dumps".to_owned(), out_of_date_dumps).into()),
+    }
+  }
+
   /// Gets the database url provided by the settings.
   pub fn get_database_url(&self) -> String {
     self.database.url.clone()
   }
 
+  /// Gets the most rows to insert into the database at once.
+  ///
+  /// A batch size of 0 could never insert anything, so it's an error.
+  pub fn get_database_batch_size(&self) -> Result<usize> {
+    match self.database.batch_size.unwrap_or(1000) {
+      0 => Err(ErrorKind::InvalidSetting("database.batch_size".to_owned(), "0".to_owned()).into()),
+      batch_size => Ok(batch_size),
+    }
+  }
+
+  /// Gets the most tables to import into the database at once.
+  pub fn get_database_max_concurrent_tables(&self) -> usize {
+    cmp::max(self.database.max_concurrent_tables.unwrap_or(8), 1)
+  }
+
   /// Gets the database type provided by the settings.
   pub fn get_database_type(&self) -> DatabaseType {
     match self.database.db_type.to_lowercase().as_str() {
@@ -126,4 +297,137 @@ impl Settings {
   pub fn get_canvas_data_api_secret(&self) -> String {
     self.canvasdataauth.api_secret.clone()
   }
+
+  /// Gets the Canvas Data API Base URL provided by the settings.
+  pub fn get_canvas_data_api_url(&self) -> String {
+    self.api_url.clone().unwrap_or(DEFAULT_API_URL.to_owned())
+  }
+
+  /// Gets the maximum number of times to retry a failed HTTP Request.
+  pub fn get_http_max_retries(&self) -> u32 {
+    self.http.as_ref().and_then(|http| http.max_retries).unwrap_or(5)
+  }
+
+  /// Gets the delay before the first retry of a failed HTTP Request in milliseconds.
+  pub fn get_http_retry_base_delay_ms(&self) -> u64 {
+    self.http.as_ref().and_then(|http| http.retry_base_delay_ms).unwrap_or(1000)
+  }
+
+  /// Gets the maximum delay between retries of a failed HTTP Request in milliseconds.
+  pub fn get_http_retry_max_delay_ms(&self) -> u64 {
+    self.http.as_ref().and_then(|http| http.retry_max_delay_ms).unwrap_or(60000)
+  }
+
+  /// Gets the timeout for connecting to a server in seconds.
+  pub fn get_http_connect_timeout_secs(&self) -> u64 {
+    self.http.as_ref().and_then(|http| http.connect_timeout_secs).unwrap_or(30)
+  }
+
+  /// Gets the timeout for each read, or write on a connection in seconds.
+  pub fn get_http_read_timeout_secs(&self) -> u64 {
+    self.http.as_ref().and_then(|http| http.read_timeout_secs).unwrap_or(300)
+  }
+
+  /// Gets the URL of the proxy to send all requests through, if there is one.
+  pub fn get_http_proxy_url(&self) -> Option<String> {
+    self.http.as_ref().and_then(|http| http.proxy_url.clone())
+  }
+
+  /// Gets the username, and password to authenticate to the proxy with, if there are any.
+  pub fn get_http_proxy_credentials(&self) -> Option<(String, String)> {
+    self.http.as_ref().and_then(|http| {
+      http.proxy_username.clone().map(|username| {
+        (username, http.proxy_password.clone().unwrap_or(String::new()))
+      })
+    })
+  }
+
+  /// Gets the paths to PEM files of extra CA certificates to trust.
+  pub fn get_http_ca_certificates(&self) -> Vec<String> {
+    self
+      .http
+      .as_ref()
+      .and_then(|http| http.ca_certificates.clone())
+      .unwrap_or(Vec::new())
+  }
+
+  /// Gets the maximum number of files to download at once.
+  pub fn get_max_concurrent_downloads(&self) -> usize {
+    self.http.as_ref().and_then(|http| http.max_concurrent_downloads).unwrap_or(4)
+  }
+
+  /// Gets the maximum number of bytes per second to download, if there is a limit.
+  ///
+  /// A limit of 0 is the same as no limit.
+  pub fn get_max_download_bytes_per_second(&self) -> Option<u64> {
+    self
+      .http
+      .as_ref()
+      .and_then(|http| http.max_download_bytes_per_second)
+      .and_then(|bytes_per_second| if bytes_per_second == 0 { None } else { Some(bytes_per_second) })
+  }
+
+  /// Gets the maximum number of downloaded tables waiting to be imported.
+  pub fn get_max_pending_tables(&self) -> usize {
+    self.http.as_ref().and_then(|http| http.max_pending_tables).unwrap_or(2)
+  }
+
+  /// Gets the number of the most recent successfully imported dumps to keep, if there's a limit.
+  pub fn get_retention_keep_last_successful(&self) -> Option<usize> {
+    self.retention.as_ref().and_then(|retention| retention.keep_last_successful)
+  }
+
+  /// Gets the notion of whether or not to remove every dump as soon as it's imported successfully.
+  pub fn get_retention_delete_after_import(&self) -> bool {
+    self
+      .retention
+      .as_ref()
+      .and_then(|retention| retention.delete_after_import)
+      .unwrap_or(false)
+  }
+
+  /// Gets the directory to move removed dumps to, if they should be archived instead of deleted.
+  pub fn get_retention_archive_location(&self) -> Option<String> {
+    self.retention.as_ref().and_then(|retention| retention.archive_location.clone())
+  }
+
+  /// Gets the OAuth Client ID, and Client Secret for the DAP API, if DAP is configured.
+  pub fn get_dap_client_credentials(&self) -> Option<(String, String)> {
+    self
+      .dap
+      .as_ref()
+      .map(|dap| (dap.client_id.clone(), dap.client_secret.clone()))
+  }
+
+  /// Gets the DAP API Base URL provided by the settings.
+  pub fn get_dap_api_url(&self) -> String {
+    self
+      .dap
+      .as_ref()
+      .and_then(|dap| dap.api_url.clone())
+      .unwrap_or(DEFAULT_DAP_API_URL.to_owned())
+  }
+
+  /// Gets the namespace of the DAP tables to sync.
+  pub fn get_dap_namespace(&self) -> String {
+    self
+      .dap
+      .as_ref()
+      .and_then(|dap| dap.namespace.clone())
+      .unwrap_or("canvas".to_owned())
+  }
+
+  /// Gets the DAP tables to sync, empty if every table in the namespace should be synced.
+  pub fn get_dap_tables(&self) -> Vec<String> {
+    self
+      .dap
+      .as_ref()
+      .and_then(|dap| dap.tables.clone())
+      .unwrap_or(Vec::new())
+  }
+
+  /// Gets how long to wait between checks on a DAP query job in seconds.
+  pub fn get_dap_poll_interval_secs(&self) -> u64 {
+    self.dap.as_ref().and_then(|dap| dap.poll_interval_secs).unwrap_or(5)
+  }
 }